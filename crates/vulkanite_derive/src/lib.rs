@@ -0,0 +1,202 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt};
+
+/// Derives `vn::VertexLayout` for a `#[repr(C)]`, `bytemuck::Pod` vertex struct.
+///
+/// Offsets are taken from the struct layout and formats from the field types
+/// (`[f32; 3]` => `Float32x3`, `u32` => `Uint32`, ...). Locations are assigned in field order.
+///
+/// - `#[location(N)]` on a field overrides its location, following fields continue from there.
+/// - `#[format(Unorm8x4)]` on a field overrides its format, e.g. for normalized formats.
+/// - `#[instance]` on the struct uses `VertexStepMode::Instance`.
+#[proc_macro_derive(VertexLayout, attributes(location, format, instance))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "VertexLayout can't be derived for generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input,
+                    "VertexLayout requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "VertexLayout can only be derived for structs",
+            ))
+        }
+    };
+
+    let step_mode = if input
+        .attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("instance"))
+    {
+        quote!(::vulkanite_vulkan::vn::VertexStepMode::Instance)
+    } else {
+        quote!(::vulkanite_vulkan::vn::VertexStepMode::Vertex)
+    };
+
+    let mut items = Vec::with_capacity(fields.len());
+    let mut attributes = Vec::with_capacity(fields.len());
+    let mut previous: Option<(Ident, Ident)> = None;
+
+    for (i, field) in fields.iter().enumerate() {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let format_name = format_ident!("FORMAT_{}", i);
+        let location_name = format_ident!("LOCATION_{}", i);
+
+        let mut location = None;
+        let mut format = None;
+        for attr in &field.attrs {
+            if attr.path().is_ident("location") {
+                location = Some(attr.parse_args::<LitInt>()?);
+            } else if attr.path().is_ident("format") {
+                format = Some(attr.parse_args::<Ident>()?);
+            }
+        }
+
+        let format = match format {
+            Some(format) => quote!(::vulkanite_vulkan::vn::VertexFormat::#format),
+            None => quote!(<#ty as ::vulkanite_vulkan::vn::VertexFormatType>::FORMAT),
+        };
+
+        let location = match (location, &previous) {
+            (Some(location), _) => quote!(#location),
+            (None, Some((previous_format, previous_location))) => {
+                quote!(#previous_location + #previous_format.locations())
+            }
+            (None, None) => quote!(0),
+        };
+
+        let size_error = format!("format of `{}` doesn't match the size of its type", ident);
+
+        items.push(quote! {
+            const #format_name: ::vulkanite_vulkan::vn::VertexFormat = #format;
+            const #location_name: ::vulkanite_vulkan::vn::ShaderLocation = #location;
+            const _: () = ::core::assert!(
+                ::core::mem::size_of::<#ty>() as ::vulkanite_vulkan::vn::BufferAddress
+                    == #format_name.size(),
+                #size_error
+            );
+        });
+
+        attributes.push(quote! {
+            ::vulkanite_vulkan::vn::VertexAttribute {
+                format: #format_name,
+                offset: ::core::mem::offset_of!(#name, #ident)
+                    as ::vulkanite_vulkan::vn::BufferAddress,
+                location: #location_name,
+            }
+        });
+
+        previous = Some((format_name, location_name));
+    }
+
+    Ok(quote! {
+        const _: fn() = || {
+            fn assert_pod<T: ::vulkanite_vulkan::__private::bytemuck::Pod>() {}
+            assert_pod::<#name>();
+        };
+
+        impl ::vulkanite_vulkan::vn::VertexLayout for #name {
+            const ATTRIBUTES: &'static [::vulkanite_vulkan::vn::VertexAttribute] = {
+                #(#items)*
+                &[#(#attributes),*]
+            };
+            const STEP_MODE: ::vulkanite_vulkan::vn::VertexStepMode = #step_mode;
+        }
+    })
+}
+
+/// Fields without a `VertexFormatType` need an explicit `#[format(..)]`:
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex {
+///     weights: [f32; 5],
+/// }
+/// ```
+///
+/// Explicit formats have to match the size of the field:
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex {
+///     #[format(Float32x4)]
+///     position: [f32; 3],
+/// }
+/// ```
+///
+/// Unknown formats are rejected:
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex {
+///     #[format(Float32x5)]
+///     position: [f32; 3],
+/// }
+/// ```
+///
+/// Only structs with named fields are supported:
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex([f32; 3], [f32; 2]);
+/// ```
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex<T: bytemuck::Pod> {
+///     position: T,
+/// }
+/// ```
+///
+/// Vertices have to be `Pod`:
+///
+/// ```compile_fail
+/// #[repr(C)]
+/// #[derive(Copy, Clone, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex {
+///     position: [f32; 3],
+/// }
+/// ```
+///
+/// The same structs compile with supported fields:
+///
+/// ```
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vulkanite_vulkan::vn::VertexLayout)]
+/// struct Vertex {
+///     #[format(Float32x4)]
+///     position: [f32; 4],
+///     weights: [f32; 4],
+/// }
+/// ```
+#[cfg(doctest)]
+struct CompileFail;
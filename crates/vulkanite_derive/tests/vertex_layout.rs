@@ -0,0 +1,66 @@
+use vulkanite_vulkan::vn::{self, VertexLayout};
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vn::VertexLayout)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+    #[format(Unorm8x4)]
+    color: [u8; 4],
+    id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, vn::VertexLayout)]
+#[instance]
+struct Instance {
+    #[location(4)]
+    offset: [f64; 3],
+    scale: f32,
+    #[location(10)]
+    layer: i32,
+    tint: [u16; 4],
+}
+
+fn attributes(layout: &vn::VertexBufferLayout<'_>) -> Vec<(vn::VertexFormat, u64, u32)> {
+    layout
+        .attributes
+        .iter()
+        .map(|attribute| (attribute.format, attribute.offset, attribute.location))
+        .collect()
+}
+
+#[test]
+fn vertex_layout() {
+    let layout = Vertex::layout();
+    assert_eq!(layout.array_stride, 40);
+    assert!(matches!(layout.step_mode, vn::VertexStepMode::Vertex));
+    assert_eq!(
+        attributes(&layout),
+        [
+            (vn::VertexFormat::Float32x3, 0, 0),
+            (vn::VertexFormat::Float32x3, 12, 1),
+            (vn::VertexFormat::Float32x2, 24, 2),
+            (vn::VertexFormat::Unorm8x4, 32, 3),
+            (vn::VertexFormat::Uint32, 36, 4),
+        ]
+    );
+}
+
+#[test]
+fn instance_layout() {
+    let layout = Instance::layout();
+    assert_eq!(layout.array_stride, 40);
+    assert!(matches!(layout.step_mode, vn::VertexStepMode::Instance));
+    // `Float64x3` takes two locations, explicit locations reset the count
+    assert_eq!(
+        attributes(&layout),
+        [
+            (vn::VertexFormat::Float64x3, 0, 4),
+            (vn::VertexFormat::Float32, 24, 6),
+            (vn::VertexFormat::Sint32, 28, 10),
+            (vn::VertexFormat::Uint16x4, 32, 11),
+        ]
+    );
+}
@@ -13,13 +13,44 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// How a buffer is used by the next commands, drives buffer barriers.
+    #[repr(transparent)]
+    pub struct BufferUses: u32 {
+        const MAP_READ = 1 << 0;
+        const MAP_WRITE = 1 << 1;
+        const COPY_SRC = 1 << 2;
+        const COPY_DST = 1 << 3;
+        const INDEX = 1 << 4;
+        const VERTEX = 1 << 5;
+        const UNIFORM = 1 << 6;
+        const STORAGE_READ = 1 << 7;
+        const STORAGE_READ_WRITE = 1 << 8;
+        const INDIRECT = 1 << 9;
+        /// Uses that only read from the buffer.
+        const INCLUSIVE = Self::MAP_READ.bits | Self::COPY_SRC.bits | Self::INDEX.bits | Self::VERTEX.bits | Self::UNIFORM.bits | Self::STORAGE_READ.bits | Self::INDIRECT.bits;
+        /// Uses that write to the buffer.
+        const EXCLUSIVE = Self::MAP_WRITE.bits | Self::COPY_DST.bits | Self::STORAGE_READ_WRITE.bits;
+    }
+}
+
 pub type BufferAddress = u64;
 pub const COPY_BUFFER_ALIGNMENT: BufferAddress = 4;
 
-// #[derive(Debug, Copy, Clone)]
-// pub enum BufferMemoryLocation {
-//     Unknown,
-//     CpuToGpu,
-//     GpuToCpu,
-//     GpuOnly,
-// }
+/// Memory a buffer or texture is allocated from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Device local memory, fastest for the device and not necessarily host visible.
+    GpuOnly,
+    /// Host visible memory written by the host and read by the device, e.g. uniform buffers.
+    CpuToGpu,
+    /// Host visible memory written by the device and read back by the host.
+    GpuToCpu,
+    /// Short-lived device memory, lazily allocated for `TextureUsages::TRANSIENT_ATTACHMENT`
+    /// textures when the device has such memory.
+    Transient,
+    /// Picked from the usage: host visible for `MAP_READ` / `MAP_WRITE` buffers and buffers
+    /// created with contents, `Transient` for transient attachments and `GpuOnly` for
+    /// everything else.
+    Auto,
+}
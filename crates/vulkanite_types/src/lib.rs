@@ -1,11 +1,13 @@
 mod buffer;
 mod image;
 mod pipeline;
+mod query;
 mod texture;
 
 pub use buffer::*;
 pub use image::*;
 pub use pipeline::*;
+pub use query::*;
 pub use texture::*;
 
 bitflags::bitflags! {
@@ -88,11 +90,18 @@ pub enum SharingMode {
     Concurrent,
 }
 
+/// `mip_level_count` covering every mip from `base_mip_level` on.
+pub const REMAINING_MIP_LEVELS: u32 = u32::MAX;
+/// `array_layer_count` covering every layer from `base_array_layer` on.
+pub const REMAINING_ARRAY_LAYERS: u32 = u32::MAX;
+
 #[derive(Debug, Copy, Clone)]
 pub struct ImageSubresourceRange {
     pub aspects: TextureAspects,
     pub base_mip_level: u32,
+    /// Can be `REMAINING_MIP_LEVELS`.
     pub mip_level_count: u32,
     pub base_array_layer: u32,
+    /// Can be `REMAINING_ARRAY_LAYERS`.
     pub array_layer_count: u32,
 }
@@ -1,4 +1,5 @@
 use crate::{BufferAddress, TextureFormat};
+use std::mem;
 use std::ops::Range;
 
 bitflags::bitflags! {
@@ -92,6 +93,16 @@ bitflags::bitflags! {
         const BLEND_CONSTANT = 1 << 0;
         const STENCIL_REFERENCE = 1 << 1;
         const WRITES_DEPTH_STENCIL = 1 << 2;
+        /// Cull mode is set with `set_cull_mode` instead of `PrimitiveState::cull_mode`.
+        const DYNAMIC_CULL_MODE = 1 << 3;
+        /// Front face is set with `set_front_face` instead of `PrimitiveState::front_face`.
+        const DYNAMIC_FRONT_FACE = 1 << 4;
+        /// Topology is set with `set_primitive_topology`,
+        /// it has to stay in the topology class of `PrimitiveState::topology`.
+        const DYNAMIC_TOPOLOGY = 1 << 5;
+        /// Depth test, depth write and compare operator are set with
+        /// `set_depth_test_enable`, `set_depth_write_enable` and `set_depth_compare`.
+        const DYNAMIC_DEPTH = 1 << 6;
     }
 
     /// Pipeline layout creation flags.
@@ -285,6 +296,19 @@ impl BlendState {
     };
 }
 
+/// One side of an explicit barrier: the stages and memory accesses it waits for or blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyncScope {
+    pub stages: StageFlags,
+    pub access: AccessFlags,
+}
+
+impl SyncScope {
+    pub fn new(stages: StageFlags, access: AccessFlags) -> Self {
+        Self { stages, access }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PushConstantRange {
     pub stages: ShaderStages,
@@ -354,6 +378,29 @@ pub struct ColorTargetState {
     pub write_mask: ColorWrites,
 }
 
+/// Value of a specialization constant (`constant_id` in GLSL).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    F32(f32),
+    F64(f64),
+}
+
+impl SpecValue {
+    /// Bytes as laid out in `VkSpecializationInfo::pData`, booleans are 32 bit.
+    pub fn to_ne_bytes(&self) -> Vec<u8> {
+        match *self {
+            SpecValue::Bool(v) => (v as u32).to_ne_bytes().to_vec(),
+            SpecValue::I32(v) => v.to_ne_bytes().to_vec(),
+            SpecValue::U32(v) => v.to_ne_bytes().to_vec(),
+            SpecValue::F32(v) => v.to_ne_bytes().to_vec(),
+            SpecValue::F64(v) => v.to_ne_bytes().to_vec(),
+        }
+    }
+}
+
 pub type ShaderLocation = u32;
 
 pub struct VertexAttribute {
@@ -440,3 +487,107 @@ pub enum VertexFormat {
     /// Four double-precision floats (f64). `dvec4` in shaders. Requires VERTEX_ATTRIBUTE_64BIT features.
     Float64x4 = 33,
 }
+
+impl VertexFormat {
+    /// Size of the format in bytes.
+    pub const fn size(&self) -> BufferAddress {
+        match self {
+            Self::Uint8x2 | Self::Sint8x2 | Self::Unorm8x2 | Self::Snorm8x2 => 2,
+            Self::Uint8x4
+            | Self::Sint8x4
+            | Self::Unorm8x4
+            | Self::Snorm8x4
+            | Self::Uint16x2
+            | Self::Sint16x2
+            | Self::Unorm16x2
+            | Self::Snorm16x2
+            | Self::Float16x2
+            | Self::Float32
+            | Self::Uint32
+            | Self::Sint32 => 4,
+            Self::Uint16x4
+            | Self::Sint16x4
+            | Self::Unorm16x4
+            | Self::Snorm16x4
+            | Self::Float16x4
+            | Self::Float32x2
+            | Self::Uint32x2
+            | Self::Sint32x2
+            | Self::Float64 => 8,
+            Self::Float32x3 | Self::Uint32x3 | Self::Sint32x3 => 12,
+            Self::Float32x4 | Self::Uint32x4 | Self::Sint32x4 | Self::Float64x2 => 16,
+            Self::Float64x3 => 24,
+            Self::Float64x4 => 32,
+        }
+    }
+
+    /// Number of shader locations the attribute occupies, `dvec3` and `dvec4` take two.
+    pub const fn locations(&self) -> ShaderLocation {
+        match self {
+            Self::Float64x3 | Self::Float64x4 => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Rust types with a natural [`VertexFormat`], used by `#[derive(VertexLayout)]`.
+/// Normalized formats have to be chosen explicitly with `#[format(..)]`.
+pub trait VertexFormatType {
+    const FORMAT: VertexFormat;
+}
+
+macro_rules! impl_vertex_format_type {
+    ($($ty:ty => $format:ident,)*) => {
+        $(
+            impl VertexFormatType for $ty {
+                const FORMAT: VertexFormat = VertexFormat::$format;
+            }
+        )*
+    };
+}
+
+impl_vertex_format_type! {
+    [u8; 2] => Uint8x2,
+    [u8; 4] => Uint8x4,
+    [i8; 2] => Sint8x2,
+    [i8; 4] => Sint8x4,
+    [u16; 2] => Uint16x2,
+    [u16; 4] => Uint16x4,
+    [i16; 2] => Sint16x2,
+    [i16; 4] => Sint16x4,
+    f32 => Float32,
+    [f32; 1] => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    u32 => Uint32,
+    [u32; 1] => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    i32 => Sint32,
+    [i32; 1] => Sint32,
+    [i32; 2] => Sint32x2,
+    [i32; 3] => Sint32x3,
+    [i32; 4] => Sint32x4,
+    f64 => Float64,
+    [f64; 1] => Float64,
+    [f64; 2] => Float64x2,
+    [f64; 3] => Float64x3,
+    [f64; 4] => Float64x4,
+}
+
+/// Vertex structs with a statically known buffer layout, usually implemented with
+/// `#[derive(VertexLayout)]`.
+pub trait VertexLayout: Sized {
+    const ATTRIBUTES: &'static [VertexAttribute];
+    const STEP_MODE: VertexStepMode;
+
+    fn layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as BufferAddress,
+            step_mode: Self::STEP_MODE,
+            attributes: Self::ATTRIBUTES,
+        }
+    }
+}
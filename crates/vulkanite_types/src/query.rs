@@ -0,0 +1,36 @@
+bitflags::bitflags! {
+    /// Counters written by a `QueryType::PipelineStatistics` query, in this order.
+    #[repr(transparent)]
+    pub struct PipelineStatisticsTypes: u32 {
+        const INPUT_ASSEMBLY_VERTICES = 1 << 0;
+        const INPUT_ASSEMBLY_PRIMITIVES = 1 << 1;
+        const VERTEX_SHADER_INVOCATIONS = 1 << 2;
+        const GEOMETRY_SHADER_INVOCATIONS = 1 << 3;
+        const GEOMETRY_SHADER_PRIMITIVES = 1 << 4;
+        const CLIPPING_INVOCATIONS = 1 << 5;
+        const CLIPPING_PRIMITIVES = 1 << 6;
+        const FRAGMENT_SHADER_INVOCATIONS = 1 << 7;
+        const TESSELLATION_CONTROL_SHADER_PATCHES = 1 << 8;
+        const TESSELLATION_EVALUATION_SHADER_INVOCATIONS = 1 << 9;
+        const COMPUTE_SHADER_INVOCATIONS = 1 << 10;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueryType {
+    Timestamp,
+    /// Number of samples that passed the depth and stencil tests.
+    Occlusion,
+    /// Requires the `pipeline_statistics_query` feature.
+    PipelineStatistics(PipelineStatisticsTypes),
+}
+
+impl QueryType {
+    /// Number of `u64` values a single query produces.
+    pub fn values_per_query(&self) -> u32 {
+        match self {
+            QueryType::Timestamp | QueryType::Occlusion => 1,
+            QueryType::PipelineStatistics(types) => types.bits().count_ones(),
+        }
+    }
+}
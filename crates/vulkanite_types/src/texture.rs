@@ -1,9 +1,10 @@
 use crate::{
-    Extent3D, ImageSubresourceRange, SharingMode, TextureDimension, TextureFormat, TextureUsages,
-    TextureViewDimension,
+    Extent3D, ImageSubresourceRange, MemoryLocation, SharingMode, TextureDimension, TextureFormat,
+    TextureUsages, TextureViewDimension,
 };
 
-pub struct TextureInfo {
+pub struct TextureInfo<'a> {
+    pub label: Option<&'a str>,
     pub dimension: TextureDimension,
     pub format: TextureFormat,
     pub size: Extent3D,
@@ -11,10 +12,41 @@ pub struct TextureInfo {
     pub samples: u32,
     pub usage: TextureUsages,
     pub sharing: SharingMode,
+    /// Queue families that access the texture when `sharing` is `SharingMode::Concurrent`.
+    pub queue_families: &'a [u32],
+    pub location: MemoryLocation,
+    /// Priority of the texture's memory from 0.0 to 1.0 when the device runs out of memory,
+    /// 0.5 by default. Gives the texture its own memory object. Needs
+    /// `Extensions::vk_ext_memory_priority`, ignored without.
+    pub memory_priority: Option<f32>,
 }
 
-pub struct TextureViewInfo {
+pub struct TextureViewInfo<'a> {
+    pub label: Option<&'a str>,
     pub dimension: TextureViewDimension,
     pub format: TextureFormat,
     pub range: ImageSubresourceRange,
 }
+
+bitflags::bitflags! {
+    /// How a texture is used by the next commands, drives layout transitions and barriers.
+    #[repr(transparent)]
+    pub struct TextureUses: u32 {
+        /// Contents are undefined, e.g. freshly created or acquired from the swapchain.
+        const UNINITIALIZED = 1 << 0;
+        const PRESENT = 1 << 1;
+        const COPY_SRC = 1 << 2;
+        const COPY_DST = 1 << 3;
+        /// Sampled in a shader.
+        const RESOURCE = 1 << 4;
+        const COLOR_TARGET = 1 << 5;
+        const DEPTH_STENCIL_READ = 1 << 6;
+        const DEPTH_STENCIL_WRITE = 1 << 7;
+        const STORAGE_READ = 1 << 8;
+        const STORAGE_READ_WRITE = 1 << 9;
+        /// Uses that only read from the texture.
+        const INCLUSIVE = Self::COPY_SRC.bits | Self::RESOURCE.bits | Self::DEPTH_STENCIL_READ.bits | Self::STORAGE_READ.bits;
+        /// Uses that write to the texture.
+        const EXCLUSIVE = Self::COPY_DST.bits | Self::COLOR_TARGET.bits | Self::DEPTH_STENCIL_WRITE.bits | Self::STORAGE_READ_WRITE.bits;
+    }
+}
@@ -1,6 +1,7 @@
 use crate::instance::Instance;
 use crate::instance::InstanceShared;
 use crate::queue::QueueFamily;
+use crate::shader::ShaderCapabilities;
 use ash::vk;
 use std::fmt;
 use std::sync::Arc;
@@ -11,6 +12,8 @@ pub struct Adapter {
     pub(crate) instance: Arc<InstanceShared>,
     pub(crate) properties: vk::PhysicalDeviceProperties,
     pub(crate) features: vk::PhysicalDeviceFeatures,
+    /// `PhysicalDeviceVulkan12Features::host_query_reset`
+    pub(crate) host_query_reset: bool,
     pub(crate) queue_families: Vec<vk::QueueFamilyProperties>,
 }
 
@@ -33,6 +36,38 @@ impl Adapter {
                 properties,
             })
     }
+
+    /// nanoseconds per timestamp query tick
+    pub fn timestamp_period(&self) -> f32 {
+        self.properties.limits.timestamp_period
+    }
+
+    /// queries can be reset from the host with `QuerySet::reset`
+    pub fn supports_host_query_reset(&self) -> bool {
+        self.host_query_reset
+    }
+
+    /// shader capabilities supported by this adapter, used to validate WGSL
+    pub fn shader_capabilities(&self) -> ShaderCapabilities {
+        let mut capabilities = ShaderCapabilities::PUSH_CONSTANT;
+        capabilities.set(
+            ShaderCapabilities::FLOAT64,
+            self.features.shader_float64 == vk::TRUE,
+        );
+        capabilities.set(
+            ShaderCapabilities::PRIMITIVE_INDEX,
+            self.features.geometry_shader == vk::TRUE,
+        );
+        capabilities.set(
+            ShaderCapabilities::CLIP_DISTANCE,
+            self.features.shader_clip_distance == vk::TRUE,
+        );
+        capabilities.set(
+            ShaderCapabilities::CULL_DISTANCE,
+            self.features.shader_cull_distance == vk::TRUE,
+        );
+        capabilities
+    }
 }
 
 impl Instance {
@@ -44,6 +79,10 @@ impl Instance {
                 let p = p.clone();
                 let properties = handle.get_physical_device_properties(p);
                 let features = handle.get_physical_device_features(p);
+                let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+                let mut features2 =
+                    vk::PhysicalDeviceFeatures2::builder().push_next(&mut features12);
+                handle.get_physical_device_features2(p, &mut features2);
                 let queue_families = handle.get_physical_device_queue_family_properties(p);
 
                 Adapter {
@@ -51,6 +90,7 @@ impl Instance {
                     instance: self.shared.clone(),
                     properties,
                     features,
+                    host_query_reset: features12.host_query_reset == vk::TRUE,
                     queue_families,
                 }
             })
@@ -1,8 +1,11 @@
 use crate::conv;
-use crate::device::{Device, DeviceError};
+use crate::deferred::{Deferred, LastUse};
+use crate::device::{Device, DeviceError, DeviceShared};
+use crate::memory::{align_up, MemoryOwner};
 use ash::vk;
 use gpu_alloc_ash::AshMemoryDevice;
 use parking_lot::Mutex;
+use std::sync::Arc;
 use vulkanite_types as vt;
 
 pub struct BufferInitInfo<'a> {
@@ -10,41 +13,94 @@ pub struct BufferInitInfo<'a> {
     pub contents: &'a [u8],
     pub usage: vt::BufferUsages,
     pub sharing: vt::SharingMode,
+    /// Queue families that access the buffer when `sharing` is `SharingMode::Concurrent`.
+    pub queue_families: &'a [u32],
+    /// `contents` are written through a mapping, `Auto` picks host visible memory for them and
+    /// `GpuOnly` or `Transient` are rejected, upload through a staging buffer instead.
+    pub location: vt::MemoryLocation,
+    /// Priority of the buffer's memory from 0.0 to 1.0 when the device runs out of memory,
+    /// 0.5 by default. Gives the buffer its own memory object. Needs
+    /// `Extensions::vk_ext_memory_priority`, ignored without.
+    pub memory_priority: Option<f32>,
 }
 
+/// Destroyed once the submissions using it have finished.
 pub struct Buffer {
+    pub(crate) device: Arc<DeviceShared>,
     pub(crate) handle: vk::Buffer,
-    pub(crate) block: Mutex<gpu_alloc::MemoryBlock<vk::DeviceMemory>>,
+    pub(crate) sharing: vt::SharingMode,
+    pub(crate) size: vt::BufferAddress,
+    /// Only `None` while the buffer is dropped.
+    pub(crate) block: Mutex<Option<gpu_alloc::MemoryBlock<vk::DeviceMemory>>>,
+    /// Last recorded use, see `TextureState` for when it is updated.
+    pub(crate) state: Arc<Mutex<vt::BufferUses>>,
+    pub(crate) last_use: Arc<LastUse>,
 }
 
 impl Device {
     pub fn create_buffer_init(&self, info: &BufferInitInfo<'_>) -> Result<Buffer, DeviceError> {
-        let size = if info.contents.is_empty() {
-            0 as vt::BufferAddress
-        } else {
-            let unpadded_size = info.contents.len() as vt::BufferAddress;
+        self.validate_sharing(info.sharing, info.queue_families)?;
 
-            let align_mask = vt::COPY_BUFFER_ALIGNMENT - 1;
-            let padded_size =
-                ((unpadded_size + align_mask) & !align_mask).max(vt::COPY_BUFFER_ALIGNMENT);
-            padded_size
-        };
+        if info.contents.is_empty() {
+            return Err(DeviceError::ZeroSizedBuffer);
+        }
+        if let vt::MemoryLocation::GpuOnly | vt::MemoryLocation::Transient = info.location {
+            return Err(DeviceError::ContentsNotHostVisible(info.location));
+        }
+        let size = align_up(
+            info.contents.len() as vt::BufferAddress,
+            vt::COPY_BUFFER_ALIGNMENT,
+        );
 
-        let vk_info = vk::BufferCreateInfo::builder()
+        self.shared.create_buffer(info, size)
+    }
+}
+
+impl DeviceShared {
+    /// Buffer of `size` bytes starting with `info.contents`, the queue families have to be
+    /// valid.
+    pub(crate) fn create_buffer(
+        self: &Arc<Self>,
+        info: &BufferInitInfo<'_>,
+        size: vt::BufferAddress,
+    ) -> Result<Buffer, DeviceError> {
+        self.check_lost()?;
+        let mut vk_info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(conv::map_buffer_usage(info.usage))
             .sharing_mode(conv::map_sharing_mode(info.sharing));
 
+        if let vt::SharingMode::Concurrent = info.sharing {
+            vk_info = vk_info.queue_family_indices(info.queue_families);
+        }
+
         let handle = unsafe {
-            self.shared
-                .handle
+            self.handle
                 .create_buffer(&vk_info, None)
                 .map_err(DeviceError::Other)?
         };
 
-        let requirements = unsafe { self.shared.handle.get_buffer_memory_requirements(handle) };
+        if let Some(label) = info.label {
+            self.set_object_name(handle, label);
+        }
+
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements =
+            vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        unsafe {
+            self.handle.get_buffer_memory_requirements2(
+                &vk::BufferMemoryRequirementsInfo2::builder().buffer(handle),
+                &mut requirements,
+            );
+        }
+        let requirements = requirements.memory_requirements;
+        let dedicated = dedicated_requirements.requires_dedicated_allocation == vk::TRUE
+            || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
+        let priority = info.memory_priority.filter(|_| self.memory_priority);
 
-        let alloc_usage = if info
+        let mut alloc_usage = if info.location != vt::MemoryLocation::Auto {
+            conv::map_memory_location(info.location)
+        } else if info
             .usage
             .intersects(vt::BufferUsages::MAP_READ | vt::BufferUsages::MAP_WRITE)
         {
@@ -61,47 +117,68 @@ impl Device {
         } else {
             gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS
         };
+        if info.location == vt::MemoryLocation::Auto && !info.contents.is_empty() {
+            alloc_usage |= gpu_alloc::UsageFlags::HOST_ACCESS;
+        }
 
         let mut allocator = self.allocator.lock();
+        let request = allocator.request(&requirements, alloc_usage);
         let mut block = unsafe {
             allocator
-                .alloc(
-                    AshMemoryDevice::wrap(&self.shared.handle),
-                    gpu_alloc::Request {
-                        size: requirements.size,
-                        align_mask: requirements.size - 1,
-                        usage: alloc_usage,
-                        memory_types: requirements.memory_type_bits,
-                    },
+                .alloc_for(
+                    &self.handle,
+                    request,
+                    MemoryOwner::Buffer(handle),
+                    dedicated,
+                    priority,
                 )
-                .unwrap()
+                .map_err(|err| {
+                    self.handle.destroy_buffer(handle, None);
+                    DeviceError::Allocation(err)
+                })?
         };
 
-        unsafe {
-            block
-                .write_bytes(AshMemoryDevice::wrap(&self.shared.handle), 0, info.contents)
-                .unwrap();
-        }
-        unsafe {
-            self.shared
-                .handle
-                .bind_buffer_memory(handle, *block.memory(), block.offset())
-                .unwrap();
+        let result = unsafe {
+            let write = if info.contents.is_empty() {
+                Ok(())
+            } else {
+                block.write_bytes(AshMemoryDevice::wrap(&self.handle), 0, info.contents)
+            };
+            write
+                .map_err(|err| DeviceError::Map(err.into()))
+                .and_then(|()| {
+                    self.handle
+                        .bind_buffer_memory(handle, *block.memory(), block.offset())
+                        .map_err(DeviceError::Other)
+                })
+        };
+        if let Err(err) = result {
+            unsafe {
+                self.handle.destroy_buffer(handle, None);
+                allocator.dealloc(&self.handle, block);
+            }
+            return Err(err);
         }
 
+        drop(allocator);
+
         Ok(Buffer {
+            device: self.clone(),
             handle,
-            block: Mutex::new(block),
+            sharing: info.sharing,
+            size,
+            block: Mutex::new(Some(block)),
+            state: Arc::new(Mutex::new(vt::BufferUses::empty())),
+            last_use: Arc::default(),
         })
     }
+}
 
-    pub fn free_buffer(&self, buffer: Buffer) {
-        unsafe {
-            self.shared.handle.destroy_buffer(buffer.handle, None);
-            self.allocator.lock().dealloc(
-                AshMemoryDevice::wrap(&self.shared.handle),
-                buffer.block.into_inner(),
-            )
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(block) = self.block.get_mut().take() {
+            self.device
+                .defer_used(self.last_use.clone(), Deferred::Buffer(self.handle, block));
         }
     }
 }
@@ -0,0 +1,135 @@
+use crate::buffer::Buffer;
+use crate::command::{CommandEncoderInfo, RenderBundle, RenderFormats, VkCommandEncoder};
+use crate::conv;
+use crate::device::{Device, DeviceError};
+use crate::pipeline::{vt, PipelineLayout, RasterPipeline};
+use crate::queue::Queue;
+use crate::types::RenderBundleInfo;
+use ash::vk;
+use parking_lot::Mutex;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Records a bundle that can be executed any number of times, also by several command buffers
+/// in flight at once. Dynamic state isn't inherited, set the viewport and scissor in the bundle.
+/// Resource uses inside bundles aren't tracked.
+pub struct RenderBundleEncoder {
+    pub(crate) handle: Arc<Mutex<VkCommandEncoder>>,
+    pub(crate) formats: RenderFormats,
+}
+
+impl RenderBundleEncoder {
+    pub fn finish(self) -> RenderBundle {
+        let mut handle = self.handle.lock();
+        let buffer = unsafe { handle.end_encoding().unwrap() };
+
+        RenderBundle {
+            handle: buffer,
+            encoder: self.handle.clone(),
+            frame: handle.frame,
+            family: handle.family,
+            formats: self.formats.clone(),
+            used: std::mem::take(&mut handle.used),
+        }
+    }
+
+    pub fn bind_raster_pipeline(&mut self, pipeline: &RasterPipeline) {
+        unsafe {
+            self.handle.lock().bind_raster_pipeline(pipeline);
+        }
+    }
+
+    pub fn set_viewport(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        min_depth: f32,
+        max_depth: f32,
+    ) {
+        unsafe {
+            self.handle.lock().set_viewport(vk::Viewport {
+                x,
+                y,
+                width,
+                height,
+                min_depth,
+                max_depth,
+            });
+        }
+    }
+
+    pub fn set_scissor_rect(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        unsafe {
+            self.handle.lock().set_scissor_rect(vk::Rect2D {
+                offset: vk::Offset2D { x, y },
+                extent: vk::Extent2D { width, height },
+            });
+        }
+    }
+
+    pub fn bind_vertex_buffer(&mut self, index: u32, buffer: &Buffer) {
+        unsafe {
+            self.handle.lock().bind_vertex_buffer(index, buffer);
+        }
+    }
+
+    pub fn push_constants(
+        &mut self,
+        layout: &PipelineLayout,
+        stages: vt::ShaderStages,
+        offset: u32,
+        data: &[u8],
+    ) {
+        unsafe {
+            self.handle.lock().push_constants(
+                layout.handle,
+                conv::map_shader_stage(stages),
+                offset,
+                data,
+            );
+        }
+    }
+
+    pub fn draw(&mut self, vertex: Range<u32>, instance: Range<u32>) {
+        let vertex_count = vertex.len() as u32;
+        let instance_count = instance.len() as u32;
+        unsafe {
+            self.handle
+                .lock()
+                .draw(vertex.start, vertex_count, instance.start, instance_count);
+        }
+    }
+}
+
+impl Device {
+    /// The bundle can only be executed by encoders of `queue`'s family, in rendering scopes
+    /// with attachments matching `info`.
+    pub fn create_render_bundle_encoder(
+        &self,
+        queue: &Queue,
+        info: &RenderBundleInfo<'_>,
+    ) -> Result<RenderBundleEncoder, DeviceError> {
+        self.shared.check_lost()?;
+        let encoder = self.create_command_encoder(CommandEncoderInfo {
+            label: None,
+            queue,
+            tracking: false,
+            frames_in_flight: 1,
+        })?;
+
+        // the pool is never reset, so the bundle stays valid as long as it is alive
+        unsafe {
+            encoder
+                .handle
+                .lock()
+                .begin_bundle(info, vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)?;
+        }
+
+        Ok(RenderBundleEncoder {
+            handle: encoder.handle,
+            formats: info.into(),
+        })
+    }
+}
@@ -1,26 +1,104 @@
 use crate::buffer::Buffer;
+use crate::color::Color;
 use crate::conv;
 use crate::conv::map_depth_attachment_info;
+use crate::deferred::{Deferred, UsedResources};
 use crate::device::{Device, DeviceError, DeviceShared};
-use crate::pipeline::{vt, PipelineLayout, RasterPipeline};
+use crate::pipeline::{vt, ComputePipeline, PipelineLayout, RasterPipeline};
+use crate::profiler::GpuProfiler;
+use crate::query::{check_queries, check_query, QuerySet};
 use crate::queue::Queue;
 use crate::surface::Frame;
-use crate::types::{ImageTransitionLayout, RenderInfo};
+use crate::texture::{subresource_runs, Texture};
+use crate::track::{self, Tracker};
+use crate::types::{ImageTransitionLayout, RenderBundleInfo, RenderInfo};
 use ash::vk;
 use parking_lot::Mutex;
-use std::ops::Range;
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::{Arc, Weak};
+use std::thread::{self, ThreadId};
 pub use vulkanite_types::{AccessFlags, StageFlags};
 
 const BUFFER_COUNT: u32 = 8;
 
+/// Command pool of one frame in flight, reset as a whole once the GPU finished its buffers.
+#[derive(Debug)]
+pub(crate) struct EncoderFrame {
+    pub(crate) pool: vk::CommandPool,
+    /// Allocated buffers that are ready to be recorded.
+    pub(crate) primary: Vec<vk::CommandBuffer>,
+    pub(crate) secondary: Vec<vk::CommandBuffer>,
+    /// Buffers recorded since the last reset.
+    pub(crate) used_primary: Vec<vk::CommandBuffer>,
+    pub(crate) used_secondary: Vec<vk::CommandBuffer>,
+    /// Value of the encoder timeline signalled by the last submission of this frame's buffers.
+    pub(crate) submitted: u64,
+}
+
 #[derive(Debug)]
 pub(crate) struct VkCommandEncoder {
-    pub(crate) handle: Mutex<vk::CommandPool>,
     pub(crate) device: Arc<DeviceShared>,
     pub(crate) active: vk::CommandBuffer,
-    pub(crate) primary: Vec<vk::CommandBuffer>,
-    pub(crate) secondary: Vec<vk::CommandBuffer>,
+    /// Buffers of the current encoding that were ended already, submitted before `active`.
+    pub(crate) finished: Vec<vk::CommandBuffer>,
+    /// Buffer holding the commands recorded before the tracked rendering scope in `active`,
+    /// kept open so barriers for the uses inside the scope are recorded ahead of it.
+    pub(crate) scope_barriers: vk::CommandBuffer,
+    pub(crate) frames: Vec<EncoderFrame>,
+    pub(crate) frame: usize,
+    /// Signalled by `Queue::submit` for every submission containing buffers of this encoder.
+    pub(crate) timeline: vk::Semaphore,
+    pub(crate) timeline_value: u64,
+    pub(crate) family: u32,
+    /// Applies to the next `begin_encoding`.
+    pub(crate) tracking: bool,
+    pub(crate) tracker: Option<Tracker>,
+    pub(crate) rendering: bool,
+    /// Encoder frames of the bundles executed by the active buffer, gated along with it on submit.
+    pub(crate) bundles: Vec<(Arc<Mutex<VkCommandEncoder>>, usize)>,
+    /// Buffers and textures recorded into the active buffer.
+    pub(crate) used: UsedResources,
+    /// Primary encoding put aside while a bundle is recorded.
+    pub(crate) suspended: Option<SuspendedEncoding>,
+    /// Attachments of the current rendering scope.
+    pub(crate) render_formats: Option<RenderFormats>,
+    /// The current rendering scope was started with `RenderInfo::bundles`.
+    pub(crate) render_bundles: bool,
+    pub(crate) profiler: Option<GpuProfiler>,
+    /// Reported in `DeviceLostInfo::last_submitted`.
+    pub(crate) label: Option<String>,
+}
+
+/// Attachment formats of a rendering scope, bundles executed in it have to match them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RenderFormats {
+    pub(crate) color_formats: Vec<vt::TextureFormat>,
+    pub(crate) depth_format: Option<vt::TextureFormat>,
+    pub(crate) sample_count: u32,
+}
+
+impl From<&RenderBundleInfo<'_>> for RenderFormats {
+    fn from(info: &RenderBundleInfo<'_>) -> Self {
+        Self {
+            color_formats: info.color_formats.to_vec(),
+            depth_format: info.depth_format,
+            sample_count: info.sample_count,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SuspendedEncoding {
+    formats: RenderFormats,
+    active: vk::CommandBuffer,
+    scope_barriers: vk::CommandBuffer,
+    tracker: Option<Tracker>,
+    rendering: bool,
+    bundles: Vec<(Arc<Mutex<VkCommandEncoder>>, usize)>,
+    used: UsedResources,
 }
 
 #[derive(Debug, Clone)]
@@ -35,15 +113,140 @@ impl CommandEncoder {
         buffer
     }
 
+    /// Moves on to the next command pool in the ring, once per frame before `begin_encoding`.
+    /// Blocks until the GPU finished all submitted buffers last recorded from that pool, then
+    /// resets it. Buffers finished from it must have been submitted by then.
+    pub fn next_frame(&mut self) -> Result<(), DeviceError> {
+        let mut handle = self.handle.lock();
+        if handle.active != vk::CommandBuffer::null() {
+            panic!("encoding still active");
+        }
+        unsafe { handle.next_frame() }
+    }
+
     pub fn begin_encoding(&mut self) {
         unsafe { self.handle.lock().begin_encoding().unwrap() }
     }
 
     fn end_encoding(&mut self) -> CommandBuffer {
-        if self.handle.lock().active == vk::CommandBuffer::null() {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe { handle.flush_barriers() };
+
+        let tracker = handle.tracker.take();
+        let mut handles = unsafe {
+            handle.end_scope_barriers().unwrap();
+            std::mem::take(&mut handle.finished)
+        };
+        handles.push(unsafe { handle.end_encoding().unwrap() });
+
+        CommandBuffer {
+            handles,
+            encoder: self.handle.clone(),
+            frame: handle.frame,
+            tracker,
+            bundles: std::mem::take(&mut handle.bundles),
+            used: std::mem::take(&mut handle.used),
+        }
+    }
+
+    /// Starts recording a bundle into a secondary command buffer, following draw calls go into
+    /// it until `finish_bundle`. Can be used while the encoder is encoding, e.g. from one
+    /// encoder per thread obtained with `Device::command_encoder`.
+    ///
+    /// Dynamic state isn't inherited, set the viewport and scissor in the bundle. Bundles are
+    /// only valid until the encoder comes back to the current frame in `next_frame`.
+    pub fn begin_bundle(&mut self, info: &RenderBundleInfo<'_>) {
+        let mut handle = self.handle.lock();
+        if handle.suspended.is_some() {
+            panic!("bundle already active");
+        }
+
+        let suspended = SuspendedEncoding {
+            formats: info.into(),
+            active: handle.active,
+            scope_barriers: std::mem::replace(
+                &mut handle.scope_barriers,
+                vk::CommandBuffer::null(),
+            ),
+            tracker: handle.tracker.take(),
+            rendering: handle.rendering,
+            bundles: std::mem::take(&mut handle.bundles),
+            used: std::mem::take(&mut handle.used),
+        };
+        handle.suspended = Some(suspended);
+
+        unsafe {
+            handle
+                .begin_bundle(info, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .unwrap()
+        }
+    }
+
+    pub fn finish_bundle(&mut self) -> RenderBundle {
+        let mut handle = self.handle.lock();
+        let suspended = match handle.suspended.take() {
+            Some(suspended) => suspended,
+            None => panic!("no active bundle"),
+        };
+
+        let buffer = unsafe { handle.end_encoding().unwrap() };
+        let used = std::mem::replace(&mut handle.used, suspended.used);
+
+        handle.active = suspended.active;
+        handle.scope_barriers = suspended.scope_barriers;
+        handle.tracker = suspended.tracker;
+        handle.rendering = suspended.rendering;
+        handle.bundles = suspended.bundles;
+
+        RenderBundle {
+            handle: buffer,
+            encoder: self.handle.clone(),
+            frame: handle.frame,
+            family: handle.family,
+            formats: suspended.formats,
+            used,
+        }
+    }
+
+    /// Executes bundles inside a rendering scope started with `RenderInfo::bundles`,
+    /// which can't contain any other draw calls.
+    /// Panics if a bundle was recorded for other attachment formats or another queue family.
+    pub fn execute_bundles(&mut self, bundles: &[RenderBundle]) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
             panic!("no active encoding");
         }
-        unsafe { self.handle.lock().end_encoding().unwrap() }
+
+        let formats = match &handle.render_formats {
+            Some(formats) => formats,
+            None => panic!("bundles can only be executed inside a rendering scope"),
+        };
+        if !handle.render_bundles {
+            panic!("bundles can only be executed in rendering scopes started with `RenderInfo::bundles`");
+        }
+        for bundle in bundles {
+            check_bundle(bundle.family, &bundle.formats, handle.family, formats);
+        }
+
+        let buffers = bundles
+            .iter()
+            .map(|bundle| bundle.handle)
+            .collect::<Vec<_>>();
+        handle.bundles.extend(
+            bundles
+                .iter()
+                .map(|bundle| (bundle.encoder.clone(), bundle.frame)),
+        );
+        for bundle in bundles {
+            handle.used.extend(&bundle.used);
+        }
+
+        unsafe {
+            handle.execute_bundles(&buffers);
+        }
     }
 
     pub fn frame_transition(
@@ -60,6 +263,7 @@ impl CommandEncoder {
         if handle.active == vk::CommandBuffer::null() {
             panic!("no active encoding");
         }
+        handle.used.add(&frame.texture.last_use);
         unsafe {
             handle.image_transition(
                 old.into(),
@@ -73,6 +277,408 @@ impl CommandEncoder {
         }
     }
 
+    /// Transitions `range` of `texture` into the layout required by `usage`,
+    /// stages and access masks are inferred from the previous and new use.
+    /// Barriers between two read-only uses in the same layout are skipped.
+    ///
+    /// With tracking enabled the barrier is deferred to the next draw, dispatch or copy.
+    pub fn transition(
+        &mut self,
+        texture: &Texture,
+        range: vt::ImageSubresourceRange,
+        usage: vt::TextureUses,
+    ) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&texture.last_use);
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_texture(texture.handle, &texture.state, range, usage);
+            return;
+        }
+
+        let barriers = texture
+            .state
+            .lock()
+            .transition(range, usage)
+            .into_iter()
+            .filter(|&(_, old)| track::texture_needs_barrier(old, usage))
+            .map(|(range, old)| track::image_barrier(texture.handle, range, old, usage))
+            .collect::<Vec<_>>();
+
+        if barriers.is_empty() {
+            return;
+        }
+
+        unsafe {
+            handle.pipeline_barrier(&[], &[], &barriers);
+        }
+    }
+
+    /// Makes writes to `range` of `buffer` in the `src` scope visible to the `dst` scope,
+    /// e.g. a compute shader writing a storage buffer that is then used as a vertex buffer.
+    /// Doesn't change the use recorded for the buffer.
+    pub fn buffer_barrier(
+        &mut self,
+        buffer: &Buffer,
+        range: impl RangeBounds<vt::BufferAddress>,
+        src: vt::SyncScope,
+        dst: vt::SyncScope,
+    ) {
+        self.pipeline_barrier(BarrierBatch::new().buffer(buffer, range, src, dst));
+    }
+
+    /// Global barrier covering all resources.
+    pub fn memory_barrier(&mut self, src: vt::SyncScope, dst: vt::SyncScope) {
+        self.pipeline_barrier(BarrierBatch::new().memory(src, dst));
+    }
+
+    /// Records all barriers in `batch` with a single `vkCmdPipelineBarrier2`.
+    /// With tracking enabled, barriers queued by the tracker are recorded along with them.
+    pub fn pipeline_barrier(&mut self, batch: BarrierBatch<'_>) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        let BarrierBatch {
+            memory,
+            mut buffers,
+            textures,
+            used,
+        } = batch;
+        handle.used.extend(&used);
+
+        let mut images = Vec::new();
+        match handle.tracker.as_mut() {
+            Some(tracker) => {
+                for (texture, range, usage) in textures {
+                    tracker.use_texture(texture.handle, &texture.state, range, usage);
+                }
+                let (pending_images, pending_buffers) = tracker.take_pending();
+                images.extend(pending_images);
+                buffers.extend(pending_buffers);
+            }
+            None => {
+                for (texture, range, usage) in textures {
+                    let runs = texture.state.lock().transition(range, usage);
+                    images.extend(
+                        runs.into_iter()
+                            .filter(|&(_, old)| track::texture_needs_barrier(old, usage))
+                            .map(|(range, old)| {
+                                track::image_barrier(texture.handle, range, old, usage)
+                            }),
+                    );
+                }
+            }
+        }
+
+        if memory.is_empty() && buffers.is_empty() && images.is_empty() {
+            return;
+        }
+
+        unsafe {
+            handle.pipeline_barrier(&memory, &buffers, &images);
+        }
+    }
+
+    /// Releases ownership of `buffer` to the family of `queue`, has to be followed by
+    /// `acquire_buffer` in a command buffer submitted to `queue` after this one executed,
+    /// e.g. by waiting on a semaphore. Only needed for `SharingMode::Exclusive` resources.
+    ///
+    /// Ownership transfers are recorded immediately, even with tracking enabled. The barrier
+    /// is built from the use left by the commands recorded before it.
+    pub fn release_buffer(&mut self, buffer: &Buffer, queue: &Queue) {
+        check_exclusive(buffer.sharing);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        handle.used.add(&buffer.last_use);
+        let usage = handle.current_buffer_use(buffer);
+        let (src_stage, src_access) = conv::map_buffer_usage_to_barrier(usage);
+        let barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(conv::map_stage_flags(src_stage))
+            .src_access_mask(conv::map_access_flags(src_access))
+            .src_queue_family_index(handle.family)
+            .dst_queue_family_index(queue.family)
+            .buffer(buffer.handle)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        unsafe {
+            handle.flush_barriers();
+            handle.pipeline_barrier(&[], &[barrier], &[]);
+        }
+    }
+
+    /// Acquires ownership of `buffer` released by `release_buffer` on `queue`.
+    pub fn acquire_buffer(&mut self, buffer: &Buffer, queue: &Queue) {
+        check_exclusive(buffer.sharing);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        handle.used.add(&buffer.last_use);
+        let usage = handle.current_buffer_use(buffer);
+        let (dst_stage, dst_access) = conv::map_buffer_usage_to_barrier(usage);
+        let barrier = vk::BufferMemoryBarrier2::builder()
+            .dst_stage_mask(conv::map_stage_flags(dst_stage))
+            .dst_access_mask(conv::map_access_flags(dst_access))
+            .src_queue_family_index(queue.family)
+            .dst_queue_family_index(handle.family)
+            .buffer(buffer.handle)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        unsafe {
+            handle.flush_barriers();
+            handle.pipeline_barrier(&[], &[barrier], &[]);
+        }
+    }
+
+    /// Releases ownership of `range` of `texture` to the family of `queue`, see `release_buffer`.
+    /// The layout is kept, transition the texture after `acquire_texture` to change it.
+    pub fn release_texture(
+        &mut self,
+        texture: &Texture,
+        range: vt::ImageSubresourceRange,
+        queue: &Queue,
+    ) {
+        check_exclusive(texture.sharing);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        handle.used.add(&texture.last_use);
+        let family = handle.family;
+        let runs = handle.current_texture_uses(texture, range);
+        let barriers = texture_ownership_barriers(texture, runs, |barrier, usage| {
+            let (src_stage, src_access) = conv::map_texture_usage_to_barrier(usage);
+            barrier
+                .src_stage_mask(conv::map_stage_flags(src_stage))
+                .src_access_mask(conv::map_access_flags(src_access))
+                .src_queue_family_index(family)
+                .dst_queue_family_index(queue.family)
+        });
+
+        unsafe {
+            handle.flush_barriers();
+            handle.pipeline_barrier(&[], &[], &barriers);
+        }
+    }
+
+    /// Acquires ownership of `range` of `texture` released by `release_texture` on `queue`.
+    pub fn acquire_texture(
+        &mut self,
+        texture: &Texture,
+        range: vt::ImageSubresourceRange,
+        queue: &Queue,
+    ) {
+        check_exclusive(texture.sharing);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        handle.used.add(&texture.last_use);
+        let family = handle.family;
+        let runs = handle.current_texture_uses(texture, range);
+        let barriers = texture_ownership_barriers(texture, runs, |barrier, usage| {
+            let (dst_stage, dst_access) = conv::map_texture_usage_to_barrier(usage);
+            barrier
+                .dst_stage_mask(conv::map_stage_flags(dst_stage))
+                .dst_access_mask(conv::map_access_flags(dst_access))
+                .src_queue_family_index(queue.family)
+                .dst_queue_family_index(family)
+        });
+
+        unsafe {
+            handle.flush_barriers();
+            handle.pipeline_barrier(&[], &[], &barriers);
+        }
+    }
+
+    /// Opens a labeled region shown by debuggers, closed by `pop_debug_group`.
+    /// No-op unless the instance was created with `debug`.
+    pub fn push_debug_group(&mut self, label: &str) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.begin_label(label, [0.0; 4]);
+        }
+    }
+
+    pub fn pop_debug_group(&mut self) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.end_label();
+        }
+    }
+
+    pub fn insert_debug_marker(&mut self, label: &str) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.insert_label(label, [0.0; 4]);
+        }
+    }
+
+    /// Marks the point reached by the GPU for `DeviceLostInfo::checkpoints`,
+    /// no-op unless `Extensions::vk_nv_device_diagnostic_checkpoints` is enabled.
+    pub fn set_checkpoint(&mut self, marker: u64) {
+        let handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        if let Some(checkpoints) = &handle.device.checkpoints {
+            unsafe {
+                checkpoints.cmd_set_checkpoint(handle.active, marker as *const ffi::c_void);
+            }
+        }
+    }
+
+    /// Writes the time at which all previous commands completed `stage` into `index` of a
+    /// timestamp query set.
+    pub fn write_timestamp(&mut self, set: &QuerySet, index: u32, stage: vt::StageFlags) {
+        assert!(
+            set.ty == vt::QueryType::Timestamp,
+            "timestamps can only be written to a timestamp query set"
+        );
+        check_query(index, set.count);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&set.last_use);
+        unsafe {
+            handle.write_timestamp(set.handle, index, conv::map_stage_flags(stage));
+        }
+    }
+
+    /// Starts an occlusion or pipeline statistics query.
+    pub fn begin_query(&mut self, set: &QuerySet, index: u32) {
+        assert!(
+            set.ty != vt::QueryType::Timestamp,
+            "timestamp queries are written with `write_timestamp`"
+        );
+        check_query(index, set.count);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&set.last_use);
+        unsafe {
+            handle.begin_query(set.handle, index);
+        }
+    }
+
+    pub fn end_query(&mut self, set: &QuerySet, index: u32) {
+        assert!(
+            set.ty != vt::QueryType::Timestamp,
+            "timestamp queries are written with `write_timestamp`"
+        );
+        check_query(index, set.count);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&set.last_use);
+        unsafe {
+            handle.end_query(set.handle, index);
+        }
+    }
+
+    /// Resets `range` on the GPU, has to happen outside of a rendering scope.
+    pub fn reset_query_set(&mut self, set: &QuerySet, range: Range<u32>) {
+        check_queries(&range, set.count);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&set.last_use);
+        unsafe {
+            handle.reset_query_set(set.handle, range);
+        }
+    }
+
+    /// Copies the values of `range` as `u64`s to `destination` at `offset`, waiting for the
+    /// queries to become available. The buffer needs `BufferUsages::COPY_DST`.
+    pub fn resolve_query_set(
+        &mut self,
+        set: &QuerySet,
+        range: Range<u32>,
+        destination: &Buffer,
+        offset: vt::BufferAddress,
+    ) {
+        check_queries(&range, set.count);
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&set.last_use);
+        handle.used.add(&destination.last_use);
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_buffer(
+                destination.handle,
+                &destination.state,
+                vt::BufferUses::COPY_DST,
+            );
+        }
+
+        let stride = 8 * set.ty.values_per_query() as vt::BufferAddress;
+        unsafe {
+            handle.flush_barriers();
+            handle.resolve_query_set(set.handle, range, destination.handle, offset, stride);
+        }
+    }
+
+    /// Declares that the following commands use `buffer` as `usage`, e.g. storage buffers read
+    /// by a dispatch. Vertex buffers and copies are declared automatically.
+    ///
+    /// With tracking enabled the barrier is deferred to the next draw, dispatch or copy.
+    pub fn use_buffer(&mut self, buffer: &Buffer, usage: vt::BufferUses) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&buffer.last_use);
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_buffer(buffer.handle, &buffer.state, usage);
+            return;
+        }
+
+        let old = std::mem::replace(&mut *buffer.state.lock(), usage);
+        if track::buffer_needs_barrier(old, usage) {
+            unsafe {
+                handle.pipeline_barrier(
+                    &[],
+                    &[track::buffer_barrier(buffer.handle, old, usage)],
+                    &[],
+                );
+            }
+        }
+    }
+
+    /// With tracking enabled the scope is recorded into a command buffer of its own, barriers
+    /// for the resources it uses are recorded ahead of it, so each resource can only be used
+    /// one way inside the scope. Queries can't be active across its start or end.
     pub fn begin_rendering(&mut self, info: RenderInfo<'_>) {
         let mut handle = self.handle.lock();
         if handle.active == vk::CommandBuffer::null() {
@@ -89,6 +695,51 @@ impl CommandEncoder {
             .depth_attachment
             .map(|depth| map_depth_attachment_info(&depth));
 
+        for attachment in info.color_attachments {
+            handle.used.add(&attachment.view.last_use);
+        }
+        if let Some(depth) = &info.depth_attachment {
+            handle.used.add(&depth.view.last_use);
+        }
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            for attachment in info.color_attachments {
+                let view = attachment.view;
+                tracker.use_texture(
+                    view.image,
+                    &view.state,
+                    view.range,
+                    vt::TextureUses::COLOR_TARGET,
+                );
+            }
+            if let Some(depth) = &info.depth_attachment {
+                let view = depth.view;
+                tracker.use_texture(
+                    view.image,
+                    &view.state,
+                    view.range,
+                    vt::TextureUses::DEPTH_STENCIL_WRITE,
+                );
+            }
+        }
+
+        let sample_count = info
+            .color_attachments
+            .first()
+            .map(|attachment| attachment.view.samples)
+            .or_else(|| info.depth_attachment.map(|depth| depth.view.samples))
+            .unwrap_or(1);
+        handle.render_formats = Some(RenderFormats {
+            color_formats: info
+                .color_attachments
+                .iter()
+                .map(|attachment| attachment.view.format)
+                .collect(),
+            depth_format: info.depth_attachment.map(|depth| depth.view.format),
+            sample_count,
+        });
+        handle.render_bundles = info.bundles;
+
         let area = vk::Rect2D {
             offset: vk::Offset2D {
                 x: info.offset.0,
@@ -101,7 +752,17 @@ impl CommandEncoder {
         };
 
         unsafe {
-            handle.begin_rendering(area, &attachments, depth, None);
+            handle.flush_barriers();
+            if let Some(tracker) = handle.tracker.as_mut() {
+                tracker.begin_scope(
+                    info.color_attachments
+                        .iter()
+                        .map(|attachment| attachment.view.image)
+                        .chain(info.depth_attachment.map(|depth| depth.view.image)),
+                );
+                handle.begin_scope_barriers().unwrap();
+            }
+            handle.begin_rendering(area, &attachments, depth, None, info.bundles);
         }
     }
 
@@ -112,6 +773,10 @@ impl CommandEncoder {
         }
         unsafe {
             handle.end_rendering();
+            handle.end_scope_barriers().unwrap();
+        }
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.end_scope();
         }
     }
 
@@ -125,14 +790,167 @@ impl CommandEncoder {
         }
     }
 
+    /// Overrides the viewport `begin_rendering` sets to the full render area.
+    pub fn set_viewport(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        min_depth: f32,
+        max_depth: f32,
+    ) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_viewport(vk::Viewport {
+                x,
+                y,
+                width,
+                height,
+                min_depth,
+                max_depth,
+            });
+        }
+    }
+
+    /// Overrides the scissor `begin_rendering` sets to the full render area.
+    pub fn set_scissor_rect(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_scissor_rect(vk::Rect2D {
+                offset: vk::Offset2D { x, y },
+                extent: vk::Extent2D { width, height },
+            });
+        }
+    }
+
+    pub fn set_blend_constant(&mut self, color: Color) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_blend_constant([color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    pub fn set_stencil_reference(&mut self, reference: u32) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_stencil_reference(reference);
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_CULL_MODE`.
+    pub fn set_cull_mode(&mut self, cull_mode: Option<vt::CullModeFlags>) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_cull_mode(cull_mode.map_or(vk::CullModeFlags::NONE, conv::map_cull_face));
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_FRONT_FACE`.
+    pub fn set_front_face(&mut self, front_face: vt::FrontFace) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_front_face(conv::map_front_face(front_face));
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_TOPOLOGY`.
+    pub fn set_primitive_topology(&mut self, topology: vt::PrimitiveTopology) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_primitive_topology(conv::map_topology(topology));
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_DEPTH`.
+    pub fn set_depth_test_enable(&mut self, enable: bool) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_depth_test_enable(enable);
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_DEPTH`.
+    pub fn set_depth_write_enable(&mut self, enable: bool) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_depth_write_enable(enable);
+        }
+    }
+
+    /// Requires a pipeline created with `PipelineFlags::DYNAMIC_DEPTH`.
+    pub fn set_depth_compare(&mut self, compare: vt::DepthCompareOperator) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.set_depth_compare(conv::map_depth_function(compare));
+        }
+    }
+
+    pub fn bind_compute_pipeline(&mut self, pipeline: &ComputePipeline) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.bind_compute_pipeline(pipeline);
+        }
+    }
+
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        unsafe {
+            handle.flush_barriers();
+            handle.dispatch(x, y, z);
+        }
+    }
+
+    /// Panics inside a rendering scope started with `RenderInfo::bundles`, unless recording a
+    /// bundle.
     pub fn draw(&mut self, vertex: Range<u32>, instance: Range<u32>) {
         let mut handle = self.handle.lock();
         if handle.active == vk::CommandBuffer::null() {
             panic!("no active encoding");
         }
+        if handle.render_bundles && handle.suspended.is_none() {
+            panic!("rendering scopes started with `RenderInfo::bundles` can only execute bundles");
+        }
         let vertex_count = vertex.len() as u32;
         let instance_count = instance.len() as u32;
         unsafe {
+            handle.flush_barriers();
             handle.draw(vertex.start, vertex_count, instance.start, instance_count);
         }
     }
@@ -143,11 +961,100 @@ impl CommandEncoder {
             panic!("no active encoding");
         }
 
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_buffer(buffer.handle, &buffer.state, vt::BufferUses::VERTEX);
+        }
+
         unsafe {
             handle.bind_vertex_buffer(index, buffer);
         }
     }
 
+    pub fn copy_buffer_to_buffer(
+        &mut self,
+        source: &Buffer,
+        source_offset: vt::BufferAddress,
+        destination: &Buffer,
+        destination_offset: vt::BufferAddress,
+        size: vt::BufferAddress,
+    ) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+        handle.used.add(&source.last_use);
+        handle.used.add(&destination.last_use);
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_buffer(source.handle, &source.state, vt::BufferUses::COPY_SRC);
+            tracker.use_buffer(
+                destination.handle,
+                &destination.state,
+                vt::BufferUses::COPY_DST,
+            );
+        }
+
+        let region = vk::BufferCopy {
+            src_offset: source_offset,
+            dst_offset: destination_offset,
+            size,
+        };
+
+        unsafe {
+            handle.flush_barriers();
+            handle.copy_buffer_to_buffer(source.handle, destination.handle, region);
+        }
+    }
+
+    /// Copies tightly packed texels into the mip level `range.base_mip_level` of `texture`.
+    /// Without tracking the texture has to be transitioned to `TextureUses::COPY_DST` first.
+    pub fn copy_buffer_to_texture(
+        &mut self,
+        source: &Buffer,
+        source_offset: vt::BufferAddress,
+        texture: &Texture,
+        range: vt::ImageSubresourceRange,
+        extent: vt::Extent3D,
+    ) {
+        let mut handle = self.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            panic!("no active encoding");
+        }
+
+        let range = vt::ImageSubresourceRange {
+            mip_level_count: 1,
+            ..range
+        };
+        handle.used.add(&source.last_use);
+        handle.used.add(&texture.last_use);
+
+        if let Some(tracker) = handle.tracker.as_mut() {
+            tracker.use_buffer(source.handle, &source.state, vt::BufferUses::COPY_SRC);
+            tracker.use_texture(
+                texture.handle,
+                &texture.state,
+                range,
+                vt::TextureUses::COPY_DST,
+            );
+        }
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(source_offset)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: conv::map_format_aspects(range.aspects),
+                mip_level: range.base_mip_level,
+                base_array_layer: range.base_array_layer,
+                layer_count: range.array_layer_count,
+            })
+            .image_extent(conv::map_extent3d(extent))
+            .build();
+
+        unsafe {
+            handle.flush_barriers();
+            handle.copy_buffer_to_texture(source.handle, texture.handle, region);
+        }
+    }
+
     pub fn push_constants(
         &mut self,
         layout: &PipelineLayout,
@@ -217,34 +1124,171 @@ impl VkCommandEncoder {
             dst_stage_mask = vk::PipelineStageFlags::from_raw(mask.bits());
         }
 
-        if let Some(mask) = src_access {
-            barrier = barrier.src_access_mask(vk::AccessFlags::from_raw(mask.bits() as vk::Flags))
-        }
-        if let Some(mask) = dst_access {
-            barrier = barrier.dst_access_mask(vk::AccessFlags::from_raw(mask.bits() as vk::Flags))
-        }
+        if let Some(mask) = src_access {
+            barrier = barrier.src_access_mask(vk::AccessFlags::from_raw(mask.bits() as vk::Flags))
+        }
+        if let Some(mask) = dst_access {
+            barrier = barrier.dst_access_mask(vk::AccessFlags::from_raw(mask.bits() as vk::Flags))
+        }
+
+        let barriers = [barrier.build()];
+        //
+        // let dependency_info = vk::DependencyInfo::builder()
+        //     .dependency_flags(vk::DependencyFlags::empty())
+        //     .image_memory_barriers(&barriers);
+        //
+        //
+        // self.device.handle.cmd_pipeline_barrier2(
+        //     self.active,
+        //     &dependency_info,
+        // );
+
+        self.device.handle.cmd_pipeline_barrier(
+            self.active,
+            src_stage_mask,
+            dst_stage_mask,
+            dependency_flags,
+            &[],
+            &[],
+            &barriers,
+        )
+    }
+
+    pub(crate) unsafe fn pipeline_barrier(
+        &mut self,
+        memory_barriers: &[vk::MemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        self.record_pipeline_barrier(
+            self.active,
+            memory_barriers,
+            buffer_barriers,
+            image_barriers,
+        );
+    }
+
+    unsafe fn record_pipeline_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        let dependency_info = vk::DependencyInfo::builder()
+            .dependency_flags(vk::DependencyFlags::empty())
+            .memory_barriers(memory_barriers)
+            .buffer_memory_barriers(buffer_barriers)
+            .image_memory_barriers(image_barriers);
+
+        self.device
+            .handle
+            .cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+
+    /// Use of `buffer` at this point of the command buffer, from the tracker if there is one.
+    fn current_buffer_use(&mut self, buffer: &Buffer) -> vt::BufferUses {
+        match self.tracker.as_mut() {
+            Some(tracker) => tracker.current_buffer_use(buffer.handle, &buffer.state),
+            None => *buffer.state.lock(),
+        }
+    }
+
+    /// Uses of `range` of `texture` at this point of the command buffer, merged into runs.
+    fn current_texture_uses(
+        &mut self,
+        texture: &Texture,
+        range: vt::ImageSubresourceRange,
+    ) -> Vec<(vt::ImageSubresourceRange, vt::TextureUses)> {
+        match self.tracker.as_mut() {
+            Some(tracker) => tracker.current_texture_uses(texture.handle, &texture.state, range),
+            None => {
+                let state = texture.state.lock();
+                let array_layers = state.array_layers;
+                subresource_runs(range, state.mip_levels, array_layers, |mip, layer| {
+                    state.uses[(mip * array_layers + layer) as usize]
+                })
+            }
+        }
+    }
+
+    /// Records the barriers queued by the tracker since the last flush. Inside a rendering
+    /// scope, where barriers aren't allowed, they go into `scope_barriers` ahead of it.
+    pub(crate) unsafe fn flush_barriers(&mut self) {
+        let pending = match self.tracker.as_ref() {
+            Some(tracker) => tracker.has_pending(),
+            None => false,
+        };
+        if !pending {
+            return;
+        }
+
+        let (image_barriers, buffer_barriers) = self.tracker.as_mut().unwrap().take_pending();
+        let buffer = if self.rendering {
+            self.scope_barriers
+        } else {
+            self.active
+        };
+        self.record_pipeline_barrier(buffer, &[], &buffer_barriers, &image_barriers);
+    }
+
+    /// Continues the encoding in a new buffer for a tracked rendering scope, the commands
+    /// recorded so far stay open in `scope_barriers` until the scope ends.
+    pub(crate) unsafe fn begin_scope_barriers(&mut self) -> Result<(), DeviceError> {
+        let buffer = self.pop_buffer(false)?;
+
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        self.device
+            .handle
+            .begin_command_buffer(buffer, &command_begin_info)
+            .map_err(DeviceError::Other)?;
+
+        self.scope_barriers = std::mem::replace(&mut self.active, buffer);
+        Ok(())
+    }
+
+    /// Ends `scope_barriers`, if there is one, and queues it for submission.
+    pub(crate) unsafe fn end_scope_barriers(&mut self) -> Result<(), DeviceError> {
+        let buffer = std::mem::replace(&mut self.scope_barriers, vk::CommandBuffer::null());
+        if buffer == vk::CommandBuffer::null() {
+            return Ok(());
+        }
+
+        self.device
+            .handle
+            .end_command_buffer(buffer)
+            .map_err(DeviceError::Other)?;
+        self.finished.push(buffer);
+        Ok(())
+    }
+
+    /// Records a separate command buffer with just `image_barriers` and `buffer_barriers`,
+    /// used to bring tracked resources into the state a submitted command buffer expects.
+    pub(crate) unsafe fn record_barriers(
+        &mut self,
+        image_barriers: &[vk::ImageMemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+    ) -> Result<vk::CommandBuffer, DeviceError> {
+        let buffer = self.pop_buffer(false)?;
+
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        self.device
+            .handle
+            .begin_command_buffer(buffer, &command_begin_info)
+            .map_err(DeviceError::Other)?;
+
+        self.record_pipeline_barrier(buffer, &[], buffer_barriers, image_barriers);
 
-        let barriers = [barrier.build()];
-        //
-        // let dependency_info = vk::DependencyInfo::builder()
-        //     .dependency_flags(vk::DependencyFlags::empty())
-        //     .image_memory_barriers(&barriers);
-        //
-        //
-        // self.device.handle.cmd_pipeline_barrier2(
-        //     self.active,
-        //     &dependency_info,
-        // );
+        self.device
+            .handle
+            .end_command_buffer(buffer)
+            .map_err(DeviceError::Other)?;
 
-        self.device.handle.cmd_pipeline_barrier(
-            self.active,
-            src_stage_mask,
-            dst_stage_mask,
-            dependency_flags,
-            &[],
-            &[],
-            &barriers,
-        )
+        Ok(buffer)
     }
 
     pub(crate) unsafe fn begin_rendering(
@@ -253,8 +1297,16 @@ impl VkCommandEncoder {
         attachments: &[vk::RenderingAttachmentInfo],
         depth_attachment: Option<vk::RenderingAttachmentInfo>,
         stencil_attachment: Option<vk::RenderingAttachmentInfo>,
+        bundles: bool,
     ) {
+        let flags = if bundles {
+            vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+        } else {
+            vk::RenderingFlags::empty()
+        };
+
         let mut render_info = vk::RenderingInfo::builder()
+            .flags(flags)
             .render_area(area)
             .color_attachments(attachments)
             .view_mask(0)
@@ -268,6 +1320,7 @@ impl VkCommandEncoder {
             render_info = render_info.stencil_attachment(stencil);
         }
 
+        // defaults covering the render area, can be overridden with set_viewport / set_scissor_rect
         let viewports = [vk::Viewport::builder()
             .width(area.extent.width as f32)
             .height(area.extent.height as f32)
@@ -285,15 +1338,182 @@ impl VkCommandEncoder {
         self.device
             .handle
             .cmd_begin_rendering(self.active, &render_info);
+        self.rendering = true;
+    }
+
+    pub(crate) unsafe fn set_viewport(&mut self, viewport: vk::Viewport) {
+        self.device
+            .handle
+            .cmd_set_viewport(self.active, 0, &[viewport]);
+    }
+
+    pub(crate) unsafe fn set_scissor_rect(&mut self, rect: vk::Rect2D) {
+        self.device.handle.cmd_set_scissor(self.active, 0, &[rect]);
+    }
+
+    pub(crate) unsafe fn set_blend_constant(&mut self, constants: [f32; 4]) {
+        self.device
+            .handle
+            .cmd_set_blend_constants(self.active, &constants);
+    }
+
+    pub(crate) unsafe fn set_stencil_reference(&mut self, reference: u32) {
+        self.device.handle.cmd_set_stencil_reference(
+            self.active,
+            vk::StencilFaceFlags::FRONT_AND_BACK,
+            reference,
+        );
+    }
+
+    pub(crate) unsafe fn set_cull_mode(&mut self, cull_mode: vk::CullModeFlags) {
+        self.device.handle.cmd_set_cull_mode(self.active, cull_mode);
+    }
+
+    pub(crate) unsafe fn set_front_face(&mut self, front_face: vk::FrontFace) {
+        self.device
+            .handle
+            .cmd_set_front_face(self.active, front_face);
+    }
+
+    pub(crate) unsafe fn set_primitive_topology(&mut self, topology: vk::PrimitiveTopology) {
+        self.device
+            .handle
+            .cmd_set_primitive_topology(self.active, topology);
+    }
+
+    pub(crate) unsafe fn set_depth_test_enable(&mut self, enable: bool) {
+        self.device
+            .handle
+            .cmd_set_depth_test_enable(self.active, enable);
+    }
+
+    pub(crate) unsafe fn set_depth_write_enable(&mut self, enable: bool) {
+        self.device
+            .handle
+            .cmd_set_depth_write_enable(self.active, enable);
+    }
+
+    pub(crate) unsafe fn set_depth_compare(&mut self, compare: vk::CompareOp) {
+        self.device
+            .handle
+            .cmd_set_depth_compare_op(self.active, compare);
     }
 
     pub(crate) unsafe fn end_rendering(&mut self) {
         self.device.handle.cmd_end_rendering(self.active);
+        self.rendering = false;
+        self.render_formats = None;
+        self.render_bundles = false;
+    }
+
+    /// No-op unless the instance was created with `debug`.
+    pub(crate) unsafe fn begin_label(&mut self, name: &str, color: [f32; 4]) {
+        if let Some(debug) = &self.device.instance.debug {
+            let name = ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            debug
+                .loader
+                .cmd_begin_debug_utils_label(self.active, &label);
+        }
+    }
+
+    pub(crate) unsafe fn end_label(&mut self) {
+        if let Some(debug) = &self.device.instance.debug {
+            debug.loader.cmd_end_debug_utils_label(self.active);
+        }
+    }
+
+    pub(crate) unsafe fn insert_label(&mut self, name: &str, color: [f32; 4]) {
+        if let Some(debug) = &self.device.instance.debug {
+            let name = ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            debug
+                .loader
+                .cmd_insert_debug_utils_label(self.active, &label);
+        }
+    }
+
+    pub(crate) unsafe fn write_timestamp(
+        &mut self,
+        pool: vk::QueryPool,
+        index: u32,
+        stage: vk::PipelineStageFlags2,
+    ) {
+        self.device
+            .handle
+            .cmd_write_timestamp2(self.active, stage, pool, index)
+    }
+
+    pub(crate) unsafe fn begin_query(&mut self, pool: vk::QueryPool, index: u32) {
+        self.device
+            .handle
+            .cmd_begin_query(self.active, pool, index, vk::QueryControlFlags::empty())
+    }
+
+    pub(crate) unsafe fn end_query(&mut self, pool: vk::QueryPool, index: u32) {
+        self.device.handle.cmd_end_query(self.active, pool, index)
+    }
+
+    pub(crate) unsafe fn reset_query_set(&mut self, pool: vk::QueryPool, range: Range<u32>) {
+        self.device
+            .handle
+            .cmd_reset_query_pool(self.active, pool, range.start, range.len() as u32)
+    }
+
+    pub(crate) unsafe fn resolve_query_set(
+        &mut self,
+        pool: vk::QueryPool,
+        range: Range<u32>,
+        destination: vk::Buffer,
+        offset: vt::BufferAddress,
+        stride: vt::BufferAddress,
+    ) {
+        self.device.handle.cmd_copy_query_pool_results(
+            self.active,
+            pool,
+            range.start,
+            range.len() as u32,
+            destination,
+            offset,
+            stride,
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+        )
+    }
+
+    pub(crate) unsafe fn copy_buffer_to_buffer(
+        &mut self,
+        source: vk::Buffer,
+        destination: vk::Buffer,
+        region: vk::BufferCopy,
+    ) {
+        self.device
+            .handle
+            .cmd_copy_buffer(self.active, source, destination, &[region])
+    }
+
+    pub(crate) unsafe fn copy_buffer_to_texture(
+        &mut self,
+        source: vk::Buffer,
+        destination: vk::Image,
+        region: vk::BufferImageCopy,
+    ) {
+        self.device.handle.cmd_copy_buffer_to_image(
+            self.active,
+            source,
+            destination,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        )
     }
 
     pub(crate) unsafe fn bind_vertex_buffer(&mut self, index: u32, buffer: &Buffer) {
+        self.used.add(&buffer.last_use);
         let vk_buffers = [buffer.handle];
-        let vk_offsets = [buffer.block.lock().offset()];
+        let vk_offsets = [buffer.block.lock().as_ref().unwrap().offset()];
         self.device
             .handle
             .cmd_bind_vertex_buffers(self.active, index, &vk_buffers, &vk_offsets)
@@ -312,11 +1532,7 @@ impl VkCommandEncoder {
     }
 
     pub(crate) unsafe fn begin_encoding(&mut self) -> Result<(), DeviceError> {
-        if self.primary.is_empty() {
-            self.allocate(BUFFER_COUNT, false)?
-        }
-
-        let active = self.primary.pop().unwrap();
+        let active = self.pop_buffer(false)?;
 
         let command_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
@@ -327,11 +1543,15 @@ impl VkCommandEncoder {
             .map_err(DeviceError::Other)?;
 
         self.active = active;
+        self.finished.clear();
+        self.scope_barriers = vk::CommandBuffer::null();
+        self.tracker = self.tracking.then(Tracker::default);
+        self.used = UsedResources::default();
 
         Ok(())
     }
 
-    pub(crate) unsafe fn end_encoding(&mut self) -> Result<CommandBuffer, DeviceError> {
+    pub(crate) unsafe fn end_encoding(&mut self) -> Result<vk::CommandBuffer, DeviceError> {
         let active = self.active;
         self.active = vk::CommandBuffer::null();
 
@@ -340,7 +1560,105 @@ impl VkCommandEncoder {
             .end_command_buffer(active)
             .map_err(DeviceError::Other)?;
 
-        Ok(CommandBuffer { handle: active })
+        Ok(active)
+    }
+
+    pub(crate) unsafe fn begin_bundle(
+        &mut self,
+        info: &RenderBundleInfo<'_>,
+        usage: vk::CommandBufferUsageFlags,
+    ) -> Result<(), DeviceError> {
+        let active = self.pop_buffer(true)?;
+
+        let color_formats = info
+            .color_formats
+            .iter()
+            .map(|&format| conv::map_texture_format(format))
+            .collect::<Vec<_>>();
+        let depth_format = info
+            .depth_format
+            .map_or(vk::Format::UNDEFINED, conv::map_texture_format);
+
+        let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+            .color_attachment_formats(&color_formats)
+            .depth_attachment_format(depth_format)
+            .rasterization_samples(vk::SampleCountFlags::from_raw(info.sample_count));
+        let inheritance_info =
+            vk::CommandBufferInheritanceInfo::builder().push_next(&mut rendering_info);
+
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(usage | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+            .inheritance_info(&inheritance_info);
+
+        self.device
+            .handle
+            .begin_command_buffer(active, &command_begin_info)
+            .map_err(DeviceError::Other)?;
+
+        self.active = active;
+        self.rendering = true;
+        self.used = UsedResources::default();
+
+        Ok(())
+    }
+
+    pub(crate) unsafe fn execute_bundles(&mut self, buffers: &[vk::CommandBuffer]) {
+        self.device
+            .handle
+            .cmd_execute_commands(self.active, buffers)
+    }
+
+    pub(crate) unsafe fn next_frame(&mut self) -> Result<(), DeviceError> {
+        self.frame = (self.frame + 1) % self.frames.len();
+        let frame = &mut self.frames[self.frame];
+
+        if frame.submitted > 0 {
+            let semaphores = [self.timeline];
+            let values = [frame.submitted];
+            let info = vk::SemaphoreWaitInfo::builder()
+                .semaphores(&semaphores)
+                .values(&values);
+            self.device
+                .handle
+                .wait_semaphores(&info, u64::MAX)
+                .map_err(|err| self.device.map_err(err, None))?;
+        }
+
+        self.device
+            .handle
+            .reset_command_pool(frame.pool, vk::CommandPoolResetFlags::empty())
+            .map_err(DeviceError::Other)?;
+
+        frame.primary.append(&mut frame.used_primary);
+        frame.secondary.append(&mut frame.used_secondary);
+
+        Ok(())
+    }
+
+    /// Takes a buffer from the current frame, allocating more if it ran out.
+    unsafe fn pop_buffer(&mut self, secondary: bool) -> Result<vk::CommandBuffer, DeviceError> {
+        let frame = &self.frames[self.frame];
+        let free = if secondary {
+            &frame.secondary
+        } else {
+            &frame.primary
+        };
+        if free.is_empty() {
+            self.allocate(BUFFER_COUNT, secondary)?
+        }
+
+        let frame = &mut self.frames[self.frame];
+        let buffer = if secondary {
+            let buffer = frame.secondary.pop().unwrap();
+            frame.used_secondary.push(buffer);
+            buffer
+        } else {
+            let buffer = frame.primary.pop().unwrap();
+            frame.used_primary.push(buffer);
+            buffer
+        };
+
+        Ok(buffer)
     }
 
     pub(crate) unsafe fn bind_raster_pipeline(&mut self, pipeline: &RasterPipeline) {
@@ -351,6 +1669,18 @@ impl VkCommandEncoder {
         )
     }
 
+    pub(crate) unsafe fn bind_compute_pipeline(&mut self, pipeline: &ComputePipeline) {
+        self.device.handle.cmd_bind_pipeline(
+            self.active,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.handle,
+        )
+    }
+
+    pub(crate) unsafe fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+        self.device.handle.cmd_dispatch(self.active, x, y, z)
+    }
+
     pub(crate) unsafe fn draw(
         &mut self,
         start_vertex: u32,
@@ -368,17 +1698,15 @@ impl VkCommandEncoder {
     }
 
     unsafe fn allocate(&mut self, count: u32, secondary: bool) -> Result<(), DeviceError> {
-        let buffer_info = {
-            let handle = self.handle.lock();
-            vk::CommandBufferAllocateInfo::builder()
-                .command_pool(*handle)
-                .command_buffer_count(count)
-                .level(if secondary {
-                    vk::CommandBufferLevel::SECONDARY
-                } else {
-                    vk::CommandBufferLevel::PRIMARY
-                })
-        };
+        let frame = &mut self.frames[self.frame];
+        let buffer_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(frame.pool)
+            .command_buffer_count(count)
+            .level(if secondary {
+                vk::CommandBufferLevel::SECONDARY
+            } else {
+                vk::CommandBufferLevel::PRIMARY
+            });
 
         let buffers = self
             .device
@@ -387,54 +1715,160 @@ impl VkCommandEncoder {
             .map_err(DeviceError::Other)?;
 
         if secondary {
-            self.primary.extend(buffers);
+            frame.secondary.extend(buffers);
         } else {
-            self.primary.extend(buffers);
+            frame.primary.extend(buffers);
         }
 
         Ok(())
     }
 }
 
+/// Encoders handed out by `Device::command_encoder`, per queue, thread and `tracking`.
+pub(crate) type EncoderCache = Mutex<HashMap<(u64, ThreadId, bool), CommandEncoder>>;
+
+thread_local! {
+    static THREAD_ENCODERS: ThreadEncoders = ThreadEncoders {
+        thread: thread::current().id(),
+        caches: RefCell::new(Vec::new()),
+    };
+}
+
+/// Caches holding an encoder of this thread, the encoders are evicted when the thread exits.
+struct ThreadEncoders {
+    thread: ThreadId,
+    caches: RefCell<Vec<Weak<EncoderCache>>>,
+}
+
+impl ThreadEncoders {
+    fn register(&self, cache: &Arc<EncoderCache>) {
+        let mut caches = self.caches.borrow_mut();
+        caches.retain(|cache| cache.strong_count() > 0);
+        if !caches
+            .iter()
+            .any(|registered| registered.as_ptr() == Arc::as_ptr(cache))
+        {
+            caches.push(Arc::downgrade(cache));
+        }
+    }
+}
+
+impl Drop for ThreadEncoders {
+    fn drop(&mut self) {
+        for cache in self.caches.get_mut().drain(..) {
+            if let Some(cache) = cache.upgrade() {
+                cache
+                    .lock()
+                    .retain(|&(_, thread, _), _| thread != self.thread);
+            }
+        }
+    }
+}
+
 impl Device {
-    pub fn command_encoder(&self, info: CommandEncoderInfo<'_>) -> CommandEncoder {
+    /// Encoder shared by everyone asking for one on the same queue with the same `tracking`
+    /// from the same thread, `label` and `frames_in_flight` only apply when it is first
+    /// created. The device keeps it until the thread exits.
+    pub fn command_encoder(
+        &self,
+        info: CommandEncoderInfo<'_>,
+    ) -> Result<CommandEncoder, DeviceError> {
+        self.shared.check_lost()?;
         let mut encoders = self.command_encoders.lock();
-        let id = info.queue.id();
+        let id = (info.queue.id(), thread::current().id(), info.tracking);
 
-        if encoders.contains_key(&id) {
-            return encoders.get(&id).unwrap().clone();
+        if let Some(encoder) = encoders.get(&id) {
+            return Ok(encoder.clone());
         }
 
-        let encoder = self.allocate_command_encoder(info).unwrap();
-        encoders.insert(id, encoder);
-        encoders.get(&id).unwrap().clone()
+        let encoder = self.create_command_encoder(info)?;
+        // a thread that is already exiting can't evict, it gets an encoder of its own
+        if THREAD_ENCODERS
+            .try_with(|thread| thread.register(&self.command_encoders))
+            .is_err()
+        {
+            return Ok(encoder);
+        }
+        encoders.insert(id, encoder.clone());
+        Ok(encoder)
     }
 
-    fn allocate_command_encoder(
+    /// New encoder with its own command pools, not shared through `command_encoder`.
+    pub fn create_command_encoder(
         &self,
         info: CommandEncoderInfo<'_>,
     ) -> Result<CommandEncoder, DeviceError> {
-        let CommandEncoderInfo { queue } = info;
+        self.shared.check_lost()?;
+        let CommandEncoderInfo {
+            label,
+            queue,
+            tracking,
+            frames_in_flight,
+        } = info;
 
         let command_pool_info = vk::CommandPoolCreateInfo::builder()
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
             .queue_family_index(queue.family);
 
-        let handle = unsafe {
+        let mut frames = Vec::with_capacity(frames_in_flight.max(1) as usize);
+        for _ in 0..frames_in_flight.max(1) {
+            let pool = unsafe {
+                self.shared
+                    .handle
+                    .create_command_pool(&command_pool_info, None)
+                    .map_err(DeviceError::Other)?
+            };
+            if let Some(label) = label {
+                self.shared.set_object_name(pool, label);
+            }
+            frames.push(EncoderFrame {
+                pool,
+                primary: vec![],
+                secondary: vec![],
+                used_primary: vec![],
+                used_secondary: vec![],
+                submitted: 0,
+            });
+        }
+
+        let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut timeline_info);
+
+        let timeline = unsafe {
             self.shared
                 .handle
-                .create_command_pool(&command_pool_info, None)
+                .create_semaphore(&semaphore_info, None)
                 .map_err(DeviceError::Other)?
         };
+        if let Some(label) = label {
+            self.shared
+                .set_object_name(timeline, &format!("{} timeline", label));
+        }
 
         let device = self.shared.clone();
 
         let vk_command_encoder = VkCommandEncoder {
-            handle: Mutex::new(handle),
             device: device.clone(),
             active: vk::CommandBuffer::null(),
-            primary: vec![],
-            secondary: vec![],
+            finished: vec![],
+            scope_barriers: vk::CommandBuffer::null(),
+            frames,
+            frame: 0,
+            timeline,
+            timeline_value: 0,
+            family: queue.family,
+            tracking,
+            tracker: None,
+            rendering: false,
+            bundles: vec![],
+            used: UsedResources::default(),
+            suspended: None,
+            render_formats: None,
+            render_bundles: false,
+            profiler: None,
+            label: label.map(str::to_string),
         };
 
         Ok(CommandEncoder {
@@ -443,36 +1877,305 @@ impl Device {
     }
 }
 
+/// Panics unless a bundle recorded for `family` and `formats` can be executed in a rendering
+/// scope of `scope_family` rendering to `scope_formats`.
+fn check_bundle(
+    family: u32,
+    formats: &RenderFormats,
+    scope_family: u32,
+    scope_formats: &RenderFormats,
+) {
+    if family != scope_family {
+        panic!(
+            "bundle recorded for queue family {}, executed on {}",
+            family, scope_family
+        );
+    }
+    if formats != scope_formats {
+        panic!(
+            "bundle recorded for {:?}, executed while rendering to {:?}",
+            formats, scope_formats
+        );
+    }
+}
+
+/// Concurrent resources are accessible from every listed family without transfers.
+fn check_exclusive(sharing: vt::SharingMode) {
+    if let vt::SharingMode::Concurrent = sharing {
+        panic!("ownership transfers are only valid for `SharingMode::Exclusive` resources");
+    }
+}
+
+/// One barrier per run of subresources in the same state, keeping their layout.
+fn texture_ownership_barriers(
+    texture: &Texture,
+    runs: Vec<(vt::ImageSubresourceRange, vt::TextureUses)>,
+    f: impl Fn(
+        vk::ImageMemoryBarrier2Builder<'static>,
+        vt::TextureUses,
+    ) -> vk::ImageMemoryBarrier2Builder<'static>,
+) -> Vec<vk::ImageMemoryBarrier2> {
+    runs.into_iter()
+        .map(|(range, usage)| {
+            let layout = conv::derive_image_layout(usage, range.aspects);
+            let barrier = vk::ImageMemoryBarrier2::builder()
+                .old_layout(layout)
+                .new_layout(layout)
+                .image(texture.handle)
+                .subresource_range(conv::map_texture_subresource_range(range));
+            f(barrier, usage).build()
+        })
+        .collect()
+}
+
+/// Offset and size of a buffer barrier covering `range`, `WHOLE_SIZE` without an end.
+///
+/// # Panics
+///
+/// If `range` is empty or inverted.
+fn barrier_range(
+    range: impl RangeBounds<vt::BufferAddress>,
+) -> (vt::BufferAddress, vt::BufferAddress) {
+    let offset = match range.start_bound() {
+        Bound::Included(&start) => Some(start),
+        Bound::Excluded(&start) => start.checked_add(1),
+        Bound::Unbounded => Some(0),
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1),
+        Bound::Excluded(&end) => Some(end),
+        Bound::Unbounded => {
+            return (
+                offset.expect("barrier range starts past the end"),
+                vk::WHOLE_SIZE,
+            )
+        }
+    };
+    match (offset, end) {
+        (Some(offset), Some(end)) if offset < end => (offset, end - offset),
+        // `..=u64::MAX` and the like reach to the end of any buffer
+        (Some(offset), None) => (offset, vk::WHOLE_SIZE),
+        _ => panic!(
+            "empty or inverted barrier range {:?}..{:?}",
+            range.start_bound(),
+            range.end_bound()
+        ),
+    }
+}
+
+/// Barriers recorded together by `CommandEncoder::pipeline_barrier`.
+#[derive(Default)]
+pub struct BarrierBatch<'a> {
+    memory: Vec<vk::MemoryBarrier2>,
+    buffers: Vec<vk::BufferMemoryBarrier2>,
+    textures: Vec<(&'a Texture, vt::ImageSubresourceRange, vt::TextureUses)>,
+    used: UsedResources,
+}
+
+impl<'a> BarrierBatch<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn memory(mut self, src: vt::SyncScope, dst: vt::SyncScope) -> Self {
+        self.memory.push(
+            vk::MemoryBarrier2::builder()
+                .src_stage_mask(conv::map_stage_flags(src.stages))
+                .src_access_mask(conv::map_access_flags(src.access))
+                .dst_stage_mask(conv::map_stage_flags(dst.stages))
+                .dst_access_mask(conv::map_access_flags(dst.access))
+                .build(),
+        );
+        self
+    }
+
+    pub fn buffer(
+        mut self,
+        buffer: &Buffer,
+        range: impl RangeBounds<vt::BufferAddress>,
+        src: vt::SyncScope,
+        dst: vt::SyncScope,
+    ) -> Self {
+        let (offset, size) = barrier_range(range);
+
+        self.used.add(&buffer.last_use);
+        self.buffers.push(
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(conv::map_stage_flags(src.stages))
+                .src_access_mask(conv::map_access_flags(src.access))
+                .dst_stage_mask(conv::map_stage_flags(dst.stages))
+                .dst_access_mask(conv::map_access_flags(dst.access))
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer.handle)
+                .offset(offset)
+                .size(size)
+                .build(),
+        );
+        self
+    }
+
+    /// Same as `CommandEncoder::transition`, stages, access masks and layouts are inferred.
+    pub fn texture(
+        mut self,
+        texture: &'a Texture,
+        range: vt::ImageSubresourceRange,
+        usage: vt::TextureUses,
+    ) -> Self {
+        self.used.add(&texture.last_use);
+        self.textures.push((texture, range, usage));
+        self
+    }
+}
+
 pub struct CommandBuffer {
+    /// Submitted in order, tracked rendering scopes start a new buffer.
+    pub(crate) handles: Vec<vk::CommandBuffer>,
+    pub(crate) encoder: Arc<Mutex<VkCommandEncoder>>,
+    /// Frame of the encoder the buffer was recorded from.
+    pub(crate) frame: usize,
+    /// Resource uses recorded with tracking, resolved by `Queue::submit`.
+    pub(crate) tracker: Option<Tracker>,
+    pub(crate) bundles: Vec<(Arc<Mutex<VkCommandEncoder>>, usize)>,
+    /// Dropped buffers and textures aren't destroyed while this buffer may still be submitted.
+    pub(crate) used: UsedResources,
+}
+
+/// Draw calls recorded in a secondary command buffer, see `CommandEncoder::begin_bundle` and
+/// `Device::create_render_bundle_encoder`.
+pub struct RenderBundle {
     pub(crate) handle: vk::CommandBuffer,
+    pub(crate) encoder: Arc<Mutex<VkCommandEncoder>>,
+    pub(crate) frame: usize,
+    pub(crate) family: u32,
+    pub(crate) formats: RenderFormats,
+    /// Kept while the bundle exists, it may be executed again.
+    pub(crate) used: UsedResources,
 }
 
 pub struct CommandEncoderInfo<'q> {
+    pub label: Option<&'q str>,
     pub queue: &'q Queue,
+    /// Track resource uses and insert barriers automatically, see `CommandEncoder::use_buffer`.
+    /// Tracked command buffers must be submitted in recording order.
+    pub tracking: bool,
+    /// Number of command pools cycled through by `CommandEncoder::next_frame`.
+    pub frames_in_flight: u32,
 }
 
 impl Drop for VkCommandEncoder {
     fn drop(&mut self) {
-        let handle = self.handle.lock();
-        unsafe {
-            let _ = self.device.handle.device_wait_idle();
-            // vulkan errors when buffer amount is 0, so let's just check that nothing is empty lmao
-            if self.active != vk::CommandBuffer::null() {
-                self.device
-                    .handle
-                    .free_command_buffers(*handle, &[self.active]);
-            }
-            if !self.primary.is_empty() {
-                self.device
-                    .handle
-                    .free_command_buffers(*handle, &self.primary);
-            }
-            if !self.secondary.is_empty() {
-                self.device
-                    .handle
-                    .free_command_buffers(*handle, &self.secondary);
-            }
-            self.device.handle.destroy_command_pool(*handle, None);
+        for frame in &self.frames {
+            self.device.defer(Deferred::CommandPool(frame.pool));
         }
+        self.device.defer(Deferred::Semaphore(self.timeline));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn barrier_range_bounds() {
+        assert_eq!(barrier_range(..), (0, vk::WHOLE_SIZE));
+        assert_eq!(barrier_range(4..), (4, vk::WHOLE_SIZE));
+        assert_eq!(barrier_range(4..12), (4, 8));
+        assert_eq!(barrier_range(4..=11), (4, 8));
+        assert_eq!(barrier_range(..=0), (0, 1));
+        assert_eq!(
+            barrier_range((Bound::Excluded(3), Bound::Excluded(12))),
+            (4, 8)
+        );
+        assert_eq!(barrier_range(8..=u64::MAX), (8, vk::WHOLE_SIZE));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty or inverted")]
+    fn barrier_range_rejects_empty() {
+        barrier_range(5..5);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty or inverted")]
+    fn barrier_range_rejects_inverted() {
+        #[allow(clippy::reversed_empty_ranges)]
+        barrier_range(12..4);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty or inverted")]
+    fn barrier_range_rejects_inverted_inclusive() {
+        #[allow(clippy::reversed_empty_ranges)]
+        barrier_range(12..=4);
+    }
+
+    #[test]
+    #[should_panic(expected = "starts past the end")]
+    fn barrier_range_rejects_overflowing_start() {
+        barrier_range((Bound::Excluded(u64::MAX), Bound::Unbounded));
+    }
+
+    #[test]
+    #[should_panic(expected = "only valid for `SharingMode::Exclusive`")]
+    fn concurrent_transfers_panic() {
+        check_exclusive(vt::SharingMode::Concurrent);
+    }
+
+    fn formats(color: &[vt::TextureFormat], sample_count: u32) -> RenderFormats {
+        RenderFormats::from(&RenderBundleInfo {
+            color_formats: color,
+            depth_format: Some(vt::TextureFormat::D32Sfloat),
+            sample_count,
+        })
+    }
+
+    #[test]
+    fn bundles_match_scopes_with_the_same_formats() {
+        let scope = formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1);
+        check_bundle(
+            0,
+            &formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1),
+            0,
+            &scope,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "executed while rendering to")]
+    fn bundles_reject_other_color_formats() {
+        let scope = formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1);
+        check_bundle(
+            0,
+            &formats(&[vt::TextureFormat::R8g8b8a8Unorm], 1),
+            0,
+            &scope,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "executed while rendering to")]
+    fn bundles_reject_other_attachment_counts() {
+        let scope = formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1);
+        check_bundle(0, &formats(&[], 1), 0, &scope);
+    }
+
+    #[test]
+    #[should_panic(expected = "executed while rendering to")]
+    fn bundles_reject_other_sample_counts() {
+        let scope = formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1);
+        check_bundle(
+            0,
+            &formats(&[vt::TextureFormat::B8g8r8a8Srgb], 4),
+            0,
+            &scope,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "queue family")]
+    fn bundles_reject_other_queue_families() {
+        let scope = formats(&[vt::TextureFormat::B8g8r8a8Srgb], 1);
+        check_bundle(1, &scope.clone(), 0, &scope);
     }
 }
@@ -300,6 +300,24 @@ pub fn map_buffer_usage(usage: vt::BufferUsages) -> vk::BufferUsageFlags {
     flags
 }
 
+/// `MemoryLocation::Auto` depends on the resource and is resolved by the caller.
+pub fn map_memory_location(location: vt::MemoryLocation) -> gpu_alloc::UsageFlags {
+    match location {
+        vt::MemoryLocation::GpuOnly | vt::MemoryLocation::Auto => {
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS
+        }
+        vt::MemoryLocation::CpuToGpu => {
+            gpu_alloc::UsageFlags::HOST_ACCESS | gpu_alloc::UsageFlags::UPLOAD
+        }
+        vt::MemoryLocation::GpuToCpu => {
+            gpu_alloc::UsageFlags::HOST_ACCESS | gpu_alloc::UsageFlags::DOWNLOAD
+        }
+        vt::MemoryLocation::Transient => {
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS | gpu_alloc::UsageFlags::TRANSIENT
+        }
+    }
+}
+
 pub fn map_sharing_mode(sharing: vt::SharingMode) -> vk::SharingMode {
     match sharing {
         vt::SharingMode::Exclusive => vk::SharingMode::EXCLUSIVE,
@@ -327,3 +345,172 @@ pub fn map_depth_function(depth_fn: vt::DepthCompareOperator) -> vk::CompareOp {
         vt::DepthCompareOperator::Always => vk::CompareOp::ALWAYS,
     }
 }
+
+pub fn map_stage_flags(stages: vt::StageFlags) -> vk::PipelineStageFlags2 {
+    // the first 17 bits are shared with vulkan, the rest only exist in synchronization2
+    let mut flags = vk::PipelineStageFlags2::from_raw((stages.bits() & 0x1_ffff) as vk::Flags64);
+
+    if stages.contains(vt::StageFlags::COPY) {
+        flags |= vk::PipelineStageFlags2::COPY;
+    }
+    if stages.contains(vt::StageFlags::RESOLVE) {
+        flags |= vk::PipelineStageFlags2::RESOLVE;
+    }
+    if stages.contains(vt::StageFlags::BLIT) {
+        flags |= vk::PipelineStageFlags2::BLIT;
+    }
+    if stages.contains(vt::StageFlags::CLEAR) {
+        flags |= vk::PipelineStageFlags2::CLEAR;
+    }
+    if stages.contains(vt::StageFlags::INDEX_INPUT) {
+        flags |= vk::PipelineStageFlags2::INDEX_INPUT;
+    }
+    if stages.contains(vt::StageFlags::VERTEX_ATTRIBUTE_INPUT) {
+        flags |= vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT;
+    }
+    if stages.contains(vt::StageFlags::PRE_RASTERIZATION_SHADERS) {
+        flags |= vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS;
+    }
+    flags
+}
+
+pub fn map_access_flags(access: vt::AccessFlags) -> vk::AccessFlags2 {
+    // the first 17 bits are shared with vulkan, the rest only exist in synchronization2
+    let mut flags = vk::AccessFlags2::from_raw(access.bits() & 0x1_ffff);
+
+    if access.contains(vt::AccessFlags::SHADER_SAMPLED_READ) {
+        flags |= vk::AccessFlags2::SHADER_SAMPLED_READ;
+    }
+    if access.contains(vt::AccessFlags::SHADER_STORAGE_READ) {
+        flags |= vk::AccessFlags2::SHADER_STORAGE_READ;
+    }
+    if access.contains(vt::AccessFlags::SHADER_STORAGE_WRITE) {
+        flags |= vk::AccessFlags2::SHADER_STORAGE_WRITE;
+    }
+    flags
+}
+
+pub fn derive_image_layout(usage: vt::TextureUses, aspects: vt::TextureAspects) -> vk::ImageLayout {
+    let is_color = aspects.contains(vt::TextureAspects::COLOR);
+    match usage {
+        vt::TextureUses::UNINITIALIZED => vk::ImageLayout::UNDEFINED,
+        vt::TextureUses::PRESENT => vk::ImageLayout::PRESENT_SRC_KHR,
+        vt::TextureUses::COPY_SRC => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vt::TextureUses::COPY_DST => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vt::TextureUses::RESOURCE if is_color => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vt::TextureUses::COLOR_TARGET => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        vt::TextureUses::DEPTH_STENCIL_WRITE => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        _ => {
+            if !is_color
+                && (vt::TextureUses::DEPTH_STENCIL_READ | vt::TextureUses::RESOURCE).contains(usage)
+            {
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            } else {
+                vk::ImageLayout::GENERAL
+            }
+        }
+    }
+}
+
+pub fn map_texture_usage_to_barrier(usage: vt::TextureUses) -> (vt::StageFlags, vt::AccessFlags) {
+    let mut stages = vt::StageFlags::empty();
+    let mut access = vt::AccessFlags::empty();
+    let shader_stages = vt::StageFlags::VERTEX_SHADER
+        | vt::StageFlags::FRAGMENT_SHADER
+        | vt::StageFlags::COMPUTE_SHADER;
+
+    if usage.contains(vt::TextureUses::COPY_SRC) {
+        stages |= vt::StageFlags::ALL_TRANSFER;
+        access |= vt::AccessFlags::TRANSFER_READ;
+    }
+    if usage.contains(vt::TextureUses::COPY_DST) {
+        stages |= vt::StageFlags::ALL_TRANSFER;
+        access |= vt::AccessFlags::TRANSFER_WRITE;
+    }
+    if usage.contains(vt::TextureUses::RESOURCE) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::SHADER_READ;
+    }
+    if usage.contains(vt::TextureUses::COLOR_TARGET) {
+        stages |= vt::StageFlags::COLOR_ATTACHMENT_OUTPUT;
+        access |= vt::AccessFlags::COLOR_ATTACHMENT_READ | vt::AccessFlags::COLOR_ATTACHMENT_WRITE;
+    }
+    if usage.intersects(vt::TextureUses::DEPTH_STENCIL_READ) {
+        stages |= vt::StageFlags::EARLY_FRAGMENT_TESTS | vt::StageFlags::LATE_FRAGMENT_TESTS;
+        access |= vt::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+    }
+    if usage.intersects(vt::TextureUses::DEPTH_STENCIL_WRITE) {
+        stages |= vt::StageFlags::EARLY_FRAGMENT_TESTS | vt::StageFlags::LATE_FRAGMENT_TESTS;
+        access |= vt::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vt::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    if usage.contains(vt::TextureUses::STORAGE_READ) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::SHADER_READ;
+    }
+    if usage.contains(vt::TextureUses::STORAGE_READ_WRITE) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::SHADER_READ | vt::AccessFlags::SHADER_WRITE;
+    }
+
+    if usage == vt::TextureUses::UNINITIALIZED || usage == vt::TextureUses::PRESENT {
+        (vt::StageFlags::TOP_OF_PIPE, vt::AccessFlags::empty())
+    } else {
+        (stages, access)
+    }
+}
+
+pub fn map_buffer_usage_to_barrier(usage: vt::BufferUses) -> (vt::StageFlags, vt::AccessFlags) {
+    let mut stages = vt::StageFlags::empty();
+    let mut access = vt::AccessFlags::empty();
+    let shader_stages = vt::StageFlags::VERTEX_SHADER
+        | vt::StageFlags::FRAGMENT_SHADER
+        | vt::StageFlags::COMPUTE_SHADER;
+
+    if usage.contains(vt::BufferUses::MAP_READ) {
+        stages |= vt::StageFlags::HOST;
+        access |= vt::AccessFlags::HOST_READ;
+    }
+    if usage.contains(vt::BufferUses::MAP_WRITE) {
+        stages |= vt::StageFlags::HOST;
+        access |= vt::AccessFlags::HOST_WRITE;
+    }
+    if usage.contains(vt::BufferUses::COPY_SRC) {
+        stages |= vt::StageFlags::ALL_TRANSFER;
+        access |= vt::AccessFlags::TRANSFER_READ;
+    }
+    if usage.contains(vt::BufferUses::COPY_DST) {
+        stages |= vt::StageFlags::ALL_TRANSFER;
+        access |= vt::AccessFlags::TRANSFER_WRITE;
+    }
+    if usage.contains(vt::BufferUses::UNIFORM) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::UNIFORM_READ;
+    }
+    if usage.contains(vt::BufferUses::STORAGE_READ) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::SHADER_READ;
+    }
+    if usage.contains(vt::BufferUses::STORAGE_READ_WRITE) {
+        stages |= shader_stages;
+        access |= vt::AccessFlags::SHADER_READ | vt::AccessFlags::SHADER_WRITE;
+    }
+    if usage.contains(vt::BufferUses::INDEX) {
+        stages |= vt::StageFlags::VERTEX_INPUT;
+        access |= vt::AccessFlags::INDEX_READ;
+    }
+    if usage.contains(vt::BufferUses::VERTEX) {
+        stages |= vt::StageFlags::VERTEX_INPUT;
+        access |= vt::AccessFlags::VERTEX_ATTRIBUTE_READ;
+    }
+    if usage.contains(vt::BufferUses::INDIRECT) {
+        stages |= vt::StageFlags::DRAW_INDIRECT;
+        access |= vt::AccessFlags::INDIRECT_COMMAND_READ;
+    }
+
+    if usage.is_empty() {
+        (vt::StageFlags::TOP_OF_PIPE, vt::AccessFlags::empty())
+    } else {
+        (stages, access)
+    }
+}
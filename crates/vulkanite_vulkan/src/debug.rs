@@ -1,132 +1,325 @@
 use ash::{extensions::ext, vk};
+use parking_lot::Mutex;
 use std::borrow::Cow;
-use std::{ffi, os::raw};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::{ffi, fmt, process, slice};
 use tracing::Level;
 
 pub(crate) struct VkDebug {
     pub(crate) loader: ext::DebugUtils,
     pub(crate) messenger: vk::DebugUtilsMessengerEXT,
+    /// Pointed to by the messenger's user data, has to outlive it.
+    pub(crate) user_data: Box<DebugUserData>,
 }
 
-pub struct VkDebugCallback(VkDebugFn);
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct DebugSeverities: u32 {
+        const VERBOSE = 1 << 0;
+        const INFO = 1 << 4;
+        const WARNING = 1 << 8;
+        const ERROR = 1 << 12;
+    }
+}
 
-pub type VkDebugFn = unsafe extern "system" fn(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_types: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    p_user_data: *mut raw::c_void,
-) -> vk::Bool32;
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct DebugMessageTypes: u32 {
+        const GENERAL = 1 << 0;
+        const VALIDATION = 1 << 1;
+        const PERFORMANCE = 1 << 2;
+    }
+}
+
+/// Called from inside Vulkan calls, a panic in it aborts the process.
+pub type DebugCallback = Arc<dyn Fn(DebugMessage<'_>) + Send + Sync>;
+
+/// Named object a message refers to, named through the `label` of its create info.
+#[derive(Debug, Clone)]
+pub struct DebugObject<'a> {
+    pub ty: vk::ObjectType,
+    pub handle: u64,
+    pub name: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugMessage<'a> {
+    pub severity: DebugSeverities,
+    pub ty: DebugMessageTypes,
+    pub id_name: Cow<'a, str>,
+    pub id_number: i32,
+    pub message: Cow<'a, str>,
+    pub objects: Vec<DebugObject<'a>>,
+    /// Open queue labels, innermost last.
+    pub queue_labels: Vec<Cow<'a, str>>,
+    /// Open command buffer labels, e.g. `CommandEncoder::push_debug_group`, innermost last.
+    pub cmd_labels: Vec<Cow<'a, str>>,
+}
 
-impl Default for VkDebugCallback {
+impl DebugMessage<'_> {
+    pub fn into_owned(self) -> DebugMessage<'static> {
+        let owned = |value: Cow<'_, str>| Cow::Owned(value.into_owned());
+        DebugMessage {
+            severity: self.severity,
+            ty: self.ty,
+            id_name: owned(self.id_name),
+            id_number: self.id_number,
+            message: owned(self.message),
+            objects: self
+                .objects
+                .into_iter()
+                .map(|object| DebugObject {
+                    ty: object.ty,
+                    handle: object.handle,
+                    name: object.name.map(owned),
+                })
+                .collect(),
+            queue_labels: self.queue_labels.into_iter().map(owned).collect(),
+            cmd_labels: self.cmd_labels.into_iter().map(owned).collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DebugMessengerInfo {
+    pub severities: DebugSeverities,
+    pub types: DebugMessageTypes,
+    /// Receives every message matching `severities` and `types`, messages are logged with
+    /// `tracing` when this is `None`.
+    pub callback: Option<DebugCallback>,
+    /// Stop on validation errors, meant for tests. A panic can't unwind through the
+    /// Vulkan loader, so the process is aborted after printing the message.
+    pub panic_on_validation_error: bool,
+    /// Keep validation messages around for `Instance::validation_messages`, e.g. to assert
+    /// that a test produced none.
+    pub collect_validation_messages: bool,
+}
+
+impl Default for DebugMessengerInfo {
     fn default() -> Self {
-        Self(default_vulkan_debug_callback)
+        Self {
+            severities: DebugSeverities::ERROR | DebugSeverities::WARNING | DebugSeverities::INFO,
+            types: DebugMessageTypes::all(),
+            callback: None,
+            panic_on_validation_error: false,
+            collect_validation_messages: false,
+        }
+    }
+}
+
+impl fmt::Debug for DebugMessengerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DebugMessengerInfo")
+            .field("severities", &self.severities)
+            .field("types", &self.types)
+            .field("callback", &self.callback.is_some())
+            .field("panic_on_validation_error", &self.panic_on_validation_error)
+            .field(
+                "collect_validation_messages",
+                &self.collect_validation_messages,
+            )
+            .finish()
+    }
+}
+
+pub(crate) struct DebugUserData {
+    callback: Option<DebugCallback>,
+    panic_on_validation_error: bool,
+    pub(crate) messages: Option<Mutex<Vec<DebugMessage<'static>>>>,
+}
+
+impl DebugUserData {
+    pub(crate) fn new(info: &DebugMessengerInfo) -> Box<Self> {
+        Box::new(Self {
+            callback: info.callback.clone(),
+            panic_on_validation_error: info.panic_on_validation_error,
+            messages: info
+                .collect_validation_messages
+                .then(|| Mutex::new(Vec::new())),
+        })
     }
 }
 
 impl VkDebug {
-    pub fn init(
+    /// Also chained into the instance create info, so messages of `vkCreateInstance` and
+    /// `vkDestroyInstance` reach `user_data` too.
+    pub(crate) fn create_info(
+        info: &DebugMessengerInfo,
+        user_data: &mut DebugUserData,
+    ) -> vk::DebugUtilsMessengerCreateInfoEXT {
+        vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::from_raw(
+                info.severities.bits(),
+            ))
+            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::from_raw(
+                info.types.bits(),
+            ))
+            .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(user_data as *mut DebugUserData as *mut ffi::c_void)
+            .build()
+    }
+
+    pub(crate) fn init(
         entry: &ash::Entry,
         instance: &ash::Instance,
-        callback: VkDebugCallback,
+        info: &DebugMessengerInfo,
+        mut user_data: Box<DebugUserData>,
     ) -> Result<Self, vk::Result> {
-        let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(
-                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-            )
-            .message_type(
-                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-            )
-            .pfn_user_callback(Some(callback.0));
-
+        let create_info = Self::create_info(info, &mut user_data);
         let loader = ext::DebugUtils::new(entry, instance);
 
-        let messenger = unsafe { loader.create_debug_utils_messenger(&debug_info, None)? };
+        let messenger = unsafe { loader.create_debug_utils_messenger(&create_info, None)? };
 
-        return Ok(Self { loader, messenger });
+        Ok(Self {
+            loader,
+            messenger,
+            user_data,
+        })
     }
 }
 
-unsafe extern "system" fn default_vulkan_debug_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut ffi::c_void,
-) -> vk::Bool32 {
-    let callback_data = *p_callback_data;
-    let message_id_number: i32 = callback_data.message_id_number as i32;
-
-    if std::thread::panicking() {
-        return vk::FALSE;
+unsafe fn cstr<'a>(ptr: *const ffi::c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        Cow::from("")
+    } else {
+        ffi::CStr::from_ptr(ptr).to_string_lossy()
     }
+}
 
-    let message_id_name = if callback_data.p_message_id_name.is_null() {
-        Cow::from("")
+unsafe fn raw_slice<'a, T>(ptr: *const T, count: u32) -> &'a [T] {
+    if ptr.is_null() || count == 0 {
+        &[]
     } else {
-        ffi::CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy()
+        slice::from_raw_parts(ptr, count as usize)
+    }
+}
+
+unsafe fn parse_message<'a>(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: &'a vk::DebugUtilsMessengerCallbackDataEXT,
+) -> DebugMessage<'a> {
+    let labels = |ptr, count| {
+        raw_slice::<vk::DebugUtilsLabelEXT>(ptr, count)
+            .iter()
+            .map(|label| cstr(label.p_label_name))
+            .collect()
     };
 
-    let message = if callback_data.p_message.is_null() {
-        Cow::from("")
+    DebugMessage {
+        severity: DebugSeverities::from_bits_truncate(message_severity.as_raw()),
+        ty: DebugMessageTypes::from_bits_truncate(message_type.as_raw()),
+        id_name: cstr(data.p_message_id_name),
+        id_number: data.message_id_number,
+        message: cstr(data.p_message),
+        objects: raw_slice(data.p_objects, data.object_count)
+            .iter()
+            .map(|object| DebugObject {
+                ty: object.object_type,
+                handle: object.object_handle,
+                name: (!object.p_object_name.is_null()).then(|| cstr(object.p_object_name)),
+            })
+            .collect(),
+        queue_labels: labels(data.p_queue_labels, data.queue_label_count),
+        cmd_labels: labels(data.p_cmd_buf_labels, data.cmd_buf_label_count),
+    }
+}
+
+fn log_message(message: &DebugMessage<'_>) {
+    let level = if message.severity.contains(DebugSeverities::ERROR) {
+        Level::ERROR
+    } else if message.severity.contains(DebugSeverities::WARNING) {
+        Level::WARN
+    } else if message.severity.contains(DebugSeverities::INFO) {
+        Level::INFO
     } else {
-        ffi::CStr::from_ptr(callback_data.p_message).to_string_lossy()
+        Level::DEBUG
     };
 
-    match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => {
-            tracing::event!(
-                Level::DEBUG,
-                "VULKAN: {:?}, {:?}, {}, {}",
-                message_type,
-                message_id_name,
-                &message_id_number.to_string(),
-                message
-            );
-        }
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+    macro_rules! log {
+        ($level:expr) => {
             tracing::event!(
-                Level::INFO,
+                $level,
                 "VULKAN: {:?}, {:?}, {}, {}",
-                message_type,
-                message_id_name,
-                &message_id_number.to_string(),
-                message
-            );
-        }
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
-            tracing::event!(
-                Level::WARN,
-                "VULKAN: {:?}, {:?}, {}, {}",
-                message_type,
-                message_id_name,
-                &message_id_number.to_string(),
-                message
-            );
+                message.ty,
+                message.id_name,
+                message.id_number,
+                message.message
+            )
+        };
+    }
+
+    match level {
+        Level::ERROR => log!(Level::ERROR),
+        Level::WARN => log!(Level::WARN),
+        Level::INFO => log!(Level::INFO),
+        _ => log!(Level::DEBUG),
+    }
+}
+
+unsafe extern "system" fn vulkan_debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut ffi::c_void,
+) -> vk::Bool32 {
+    if std::thread::panicking() || p_callback_data.is_null() {
+        return vk::FALSE;
+    }
+
+    // unwinding into the Vulkan loader is undefined behavior, abort instead
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        handle_message(
+            message_severity,
+            message_type,
+            &*p_callback_data,
+            (p_user_data as *const DebugUserData).as_ref(),
+        )
+    }));
+
+    match result {
+        Ok(None) => vk::FALSE,
+        Ok(Some(error)) => {
+            eprintln!("{}, aborting", error);
+            process::abort();
         }
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
-            tracing::event!(
-                Level::ERROR,
-                "VULKAN: {:?}, {:?}, {}, {}",
-                message_type,
-                message_id_name,
-                &message_id_number.to_string(),
-                message
-            );
+        Err(_) => {
+            eprintln!("Vulkan debug callback panicked, aborting");
+            process::abort();
         }
-        _ => {
-            tracing::event!(
-                Level::WARN,
-                "VULKAN: {:?}, {:?}, {}, {}",
-                message_type,
-                message_id_name,
-                &message_id_number.to_string(),
-                message
-            );
+    }
+}
+
+/// Returns the message to abort with when it is a validation error and
+/// `panic_on_validation_error` is set.
+unsafe fn handle_message(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: &vk::DebugUtilsMessengerCallbackDataEXT,
+    user_data: Option<&DebugUserData>,
+) -> Option<String> {
+    let message = parse_message(message_severity, message_type, data);
+
+    let stop = user_data.is_some_and(|data| data.panic_on_validation_error)
+        && message.severity.contains(DebugSeverities::ERROR)
+        && message.ty.contains(DebugMessageTypes::VALIDATION);
+
+    match user_data.and_then(|data| data.callback.as_ref()) {
+        Some(callback) => callback(message.clone()),
+        None => log_message(&message),
+    }
+
+    if let Some(messages) = user_data.and_then(|data| data.messages.as_ref()) {
+        if message.ty.contains(DebugMessageTypes::VALIDATION) {
+            messages.lock().push(message.clone().into_owned());
         }
-    };
+    }
 
-    vk::FALSE
+    stop.then(|| {
+        format!(
+            "Vulkan validation error {:?}: {}",
+            message.id_name, message.message
+        )
+    })
 }
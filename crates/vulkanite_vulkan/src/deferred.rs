@@ -0,0 +1,276 @@
+use crate::device::DeviceShared;
+use ash::{extensions::khr, vk};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Handle that was dropped while the GPU may still use it.
+pub(crate) enum Deferred {
+    Buffer(vk::Buffer, gpu_alloc::MemoryBlock<vk::DeviceMemory>),
+    Texture(vk::Image, gpu_alloc::MemoryBlock<vk::DeviceMemory>),
+    CommandPool(vk::CommandPool),
+    Semaphore(vk::Semaphore),
+    Fence(vk::Fence),
+    QueryPool(vk::QueryPool),
+    /// Destroyed along with the views of its images, before the surface it was created for.
+    Swapchain {
+        loader: khr::Swapchain,
+        handle: vk::SwapchainKHR,
+        views: Vec<vk::ImageView>,
+        surface: vk::SurfaceKHR,
+    },
+}
+
+/// Timeline signalled by every submission to one queue.
+#[derive(Debug)]
+pub(crate) struct QueueTimeline {
+    pub(crate) semaphore: vk::Semaphore,
+    pub(crate) submitted: u64,
+}
+
+/// Submissions using a resource, a dropped resource is destroyed once they have finished.
+#[derive(Debug, Default)]
+pub(crate) struct LastUse {
+    /// Value of every queue timeline after the last submission using the resource, indexed
+    /// like `DestroyQueue::timelines`, missing values are 0.
+    values: Mutex<Vec<u64>>,
+    /// Command buffers and bundles recording the resource that weren't submitted or dropped yet,
+    /// their submissions aren't known.
+    recording: AtomicUsize,
+}
+
+impl LastUse {
+    fn finished(&self, completed: &[u64]) -> bool {
+        self.recording.load(Ordering::Acquire) == 0
+            && self
+                .values
+                .lock()
+                .iter()
+                .zip(completed)
+                .all(|(value, done)| value <= done)
+    }
+}
+
+/// Resources recorded into a command buffer or bundle that wasn't submitted yet.
+#[derive(Debug, Default)]
+pub(crate) struct UsedResources(HashMap<usize, Arc<LastUse>>);
+
+impl UsedResources {
+    pub(crate) fn add(&mut self, last_use: &Arc<LastUse>) {
+        self.0
+            .entry(Arc::as_ptr(last_use) as usize)
+            .or_insert_with(|| {
+                last_use.recording.fetch_add(1, Ordering::AcqRel);
+                last_use.clone()
+            });
+    }
+
+    pub(crate) fn extend(&mut self, used: &UsedResources) {
+        for last_use in used.0.values() {
+            self.add(last_use);
+        }
+    }
+
+    /// Moves the last use of every resource to `value` of the queue's `timeline`, the
+    /// submission has to be queued already.
+    pub(crate) fn submitted(self, timeline: usize, value: u64) {
+        for last_use in self.0.values() {
+            let mut values = last_use.values.lock();
+            if values.len() <= timeline {
+                values.resize(timeline + 1, 0);
+            }
+            values[timeline] = values[timeline].max(value);
+        }
+    }
+}
+
+impl Drop for UsedResources {
+    fn drop(&mut self) {
+        for last_use in self.0.values() {
+            last_use.recording.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Destroys dropped resources once the submissions that used them have finished.
+///
+/// Buffers, textures and query sets wait for their `LastUse`, everything else for all submissions made
+/// before it was dropped.
+#[derive(Default)]
+pub(crate) struct DestroyQueue {
+    pub(crate) timelines: Vec<QueueTimeline>,
+    pending: Vec<(Arc<LastUse>, Deferred)>,
+}
+
+impl fmt::Debug for DestroyQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DestroyQueue")
+            .field("timelines", &self.timelines)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl DeviceShared {
+    /// Queues `resource` for destruction after the submissions made so far.
+    pub(crate) fn defer(&self, resource: Deferred) {
+        let mut destroy = self.destroy.lock();
+        let values = destroy
+            .timelines
+            .iter()
+            .map(|timeline| timeline.submitted)
+            .collect();
+        destroy.pending.push((
+            Arc::new(LastUse {
+                values: Mutex::new(values),
+                recording: AtomicUsize::new(0),
+            }),
+            resource,
+        ));
+    }
+
+    /// Queues `resource` for destruction after its last use, including command buffers
+    /// recording it that are submitted later.
+    pub(crate) fn defer_used(&self, last_use: Arc<LastUse>, resource: Deferred) {
+        self.destroy.lock().pending.push((last_use, resource));
+    }
+
+    /// Destroys the resources whose submissions have finished.
+    pub(crate) fn maintain(&self) {
+        let finished = {
+            let mut destroy = self.destroy.lock();
+            if destroy.pending.is_empty() {
+                return;
+            }
+
+            // a lost device doesn't touch anything anymore, other errors are retried by the next
+            // call
+            let mut completed = Vec::with_capacity(destroy.timelines.len());
+            for timeline in &destroy.timelines {
+                let value = if self.lost.load(Ordering::Acquire) {
+                    u64::MAX
+                } else {
+                    match unsafe { self.handle.get_semaphore_counter_value(timeline.semaphore) } {
+                        Ok(value) => value,
+                        Err(vk::Result::ERROR_DEVICE_LOST) => u64::MAX,
+                        Err(err) => {
+                            warn!("Failed to read a queue timeline: {}", err);
+                            return;
+                        }
+                    }
+                };
+                completed.push(value);
+            }
+
+            let mut finished = Vec::new();
+            for (last_use, resource) in std::mem::take(&mut destroy.pending) {
+                if last_use.finished(&completed) {
+                    finished.push(resource);
+                } else {
+                    destroy.pending.push((last_use, resource));
+                }
+            }
+            finished
+        };
+
+        for resource in finished {
+            unsafe { self.destroy(resource) };
+        }
+    }
+
+    /// Waits for the last use of `current` and the swapchains of `surface` that are still
+    /// pending, then destroys them, so the surface can be destroyed.
+    pub(crate) fn destroy_swapchains(
+        &self,
+        surface: vk::SurfaceKHR,
+        current: Option<(Arc<LastUse>, Deferred)>,
+    ) {
+        let (mut swapchains, semaphores) = {
+            let mut destroy = self.destroy.lock();
+            let (swapchains, pending) = std::mem::take(&mut destroy.pending)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(_, resource)| {
+                    matches!(resource, Deferred::Swapchain { surface: owner, .. } if *owner == surface)
+                });
+            destroy.pending = pending;
+            let semaphores = destroy
+                .timelines
+                .iter()
+                .map(|timeline| timeline.semaphore)
+                .collect::<Vec<_>>();
+            (swapchains, semaphores)
+        };
+        swapchains.extend(current);
+
+        for (last_use, resource) in swapchains {
+            let values = last_use.values.lock().clone();
+            let info = vk::SemaphoreWaitInfo::builder()
+                .semaphores(&semaphores[..values.len()])
+                .values(&values);
+            unsafe {
+                // a lost device doesn't touch the swapchain anymore
+                let _ = self.handle.wait_semaphores(&info, u64::MAX);
+                self.destroy(resource);
+            }
+        }
+    }
+
+    /// Destroys everything still pending, the device has to be idle.
+    pub(crate) unsafe fn destroy_pending(&self) {
+        let mut destroy = self.destroy.lock();
+        for (_, resource) in std::mem::take(&mut destroy.pending) {
+            self.destroy(resource);
+        }
+        for timeline in destroy.timelines.drain(..) {
+            self.handle.destroy_semaphore(timeline.semaphore, None);
+        }
+    }
+
+    unsafe fn destroy(&self, resource: Deferred) {
+        match resource {
+            Deferred::Buffer(buffer, block) => {
+                self.handle.destroy_buffer(buffer, None);
+                self.allocator.lock().dealloc(&self.handle, block);
+            }
+            Deferred::Texture(image, block) => {
+                self.handle.destroy_image(image, None);
+                self.allocator.lock().dealloc(&self.handle, block);
+            }
+            // destroying a pool frees all of its buffers
+            Deferred::CommandPool(pool) => self.handle.destroy_command_pool(pool, None),
+            Deferred::Semaphore(semaphore) => self.handle.destroy_semaphore(semaphore, None),
+            Deferred::Fence(fence) => self.handle.destroy_fence(fence, None),
+            Deferred::QueryPool(pool) => self.handle.destroy_query_pool(pool, None),
+            Deferred::Swapchain {
+                loader,
+                handle,
+                views,
+                ..
+            } => {
+                for view in views {
+                    self.handle.destroy_image_view(view, None);
+                }
+                loader.destroy_swapchain(handle, None);
+            }
+        }
+    }
+
+    /// New timeline for a queue, returns its index in the destroy queue.
+    pub(crate) fn add_queue_timeline(&self) -> usize {
+        let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::builder().push_next(&mut timeline_info);
+        let semaphore = unsafe { self.handle.create_semaphore(&info, None).unwrap() };
+
+        let mut destroy = self.destroy.lock();
+        destroy.timelines.push(QueueTimeline {
+            semaphore,
+            submitted: 0,
+        });
+        destroy.timelines.len() - 1
+    }
+}
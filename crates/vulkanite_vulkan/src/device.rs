@@ -1,23 +1,68 @@
 use crate::adapter::Adapter;
-use crate::command::CommandEncoder;
+use crate::command::EncoderCache;
+use crate::deferred::DestroyQueue;
 use crate::instance::InstanceShared;
+use crate::memory::{AllocationError, Allocator, AllocatorConfig, MapError};
 use crate::queue::Queue;
 use crate::queue::QueueCreateInfo;
 use crate::types::{Extensions, Features};
-use tracing::info;
+use tracing::{error, info};
+use vulkanite_types as vt;
 
+use ash::extensions::nv;
 use ash::vk;
 use parking_lot::Mutex;
-use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::os::raw;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{error, ffi};
+use std::{error, ffi, mem, ptr};
 
 pub(crate) struct DeviceShared {
     pub(crate) handle: ash::Device,
     pub(crate) adapter: Arc<Adapter>,
     pub(crate) instance: Arc<InstanceShared>,
+    /// Set once any call returned `ERROR_DEVICE_LOST`, the device is unusable from then on.
+    pub(crate) lost: AtomicBool,
+    pub(crate) lost_callback: Mutex<Option<DeviceLostCallback>>,
+    /// Labels of the encoders in the last submission, reported when the device is lost.
+    pub(crate) last_submitted: Mutex<Vec<String>>,
+    pub(crate) fault: Option<vk::ExtDeviceFaultFn>,
+    pub(crate) checkpoints: Option<nv::DeviceDiagnosticCheckpoints>,
+    pub(crate) allocator: Mutex<Allocator>,
+    /// `VK_EXT_memory_budget` is enabled.
+    pub(crate) memory_budget: bool,
+    /// `VK_EXT_memory_priority` is enabled.
+    pub(crate) memory_priority: bool,
+    pub(crate) destroy: Mutex<DestroyQueue>,
+}
+
+pub type DeviceLostCallback = Box<dyn Fn(&DeviceLostInfo) + Send + Sync>;
+
+/// Diagnostics gathered when the device is lost.
+#[derive(Debug, Clone)]
+pub struct DeviceLostInfo {
+    /// Labels of the command encoders in the last submission, see `CommandEncoderInfo::label`.
+    pub last_submitted: Vec<String>,
+    /// Needs `Extensions::vk_ext_device_fault`.
+    pub fault: Option<DeviceFault>,
+    /// Last checkpoints reached on the queue that reported the loss, set with
+    /// `CommandEncoder::set_checkpoint`. Needs `Extensions::vk_nv_device_diagnostic_checkpoints`.
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceFault {
+    pub description: String,
+    pub addresses: Vec<(vk::DeviceFaultAddressTypeEXT, vk::DeviceAddress)>,
+    /// Description, code and data of every vendor specific fault.
+    pub vendor: Vec<(String, u64, u64)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+    pub stage: vk::PipelineStageFlags,
+    pub marker: u64,
 }
 
 impl Debug for DeviceShared {
@@ -28,14 +73,189 @@ impl Debug for DeviceShared {
 
 pub struct Device {
     pub(crate) shared: Arc<DeviceShared>,
-    pub(crate) allocator: Mutex<gpu_alloc::GpuAllocator<vk::DeviceMemory>>,
-    pub(crate) command_encoders: Mutex<HashMap<u64, CommandEncoder>>,
+    pub(crate) command_encoders: Arc<EncoderCache>,
+}
+
+impl DeviceShared {
+    pub(crate) fn check_lost(&self) -> Result<(), DeviceError> {
+        if self.lost.load(Ordering::Acquire) {
+            Err(DeviceError::Lost)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Maps the error of a call, marking the device as lost on `ERROR_DEVICE_LOST`.
+    /// `queue` is the queue the call was made on, to read its checkpoints.
+    pub(crate) fn map_err(&self, err: vk::Result, queue: Option<vk::Queue>) -> DeviceError {
+        if err != vk::Result::ERROR_DEVICE_LOST {
+            return DeviceError::Other(err);
+        }
+        if !self.lost.swap(true, Ordering::AcqRel) {
+            let info = DeviceLostInfo {
+                last_submitted: self.last_submitted.lock().clone(),
+                fault: unsafe { self.device_fault() },
+                checkpoints: match queue {
+                    Some(queue) => unsafe { self.queue_checkpoints(queue) },
+                    None => vec![],
+                },
+            };
+            error!("Device lost: {:?}", info);
+            // taken out of the mutex, the callback may set a new one or drop resources
+            let callback = self.lost_callback.lock().take();
+            if let Some(callback) = callback {
+                callback(&info);
+            }
+        }
+        DeviceError::Lost
+    }
+
+    unsafe fn device_fault(&self) -> Option<DeviceFault> {
+        let fault = self.fault.as_ref()?;
+        let device = self.handle.handle();
+
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        (fault.get_device_fault_info_ext)(device, &mut counts, ptr::null_mut())
+            .result()
+            .ok()?;
+
+        let mut addresses =
+            vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+        let mut vendor =
+            vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+        // the vendor binary isn't read
+        counts.vendor_binary_size = 0;
+        let mut info = vk::DeviceFaultInfoEXT {
+            p_address_infos: addresses.as_mut_ptr(),
+            p_vendor_infos: vendor.as_mut_ptr(),
+            ..Default::default()
+        };
+        (fault.get_device_fault_info_ext)(device, &mut counts, &mut info)
+            .result()
+            .ok()?;
+
+        let text = |chars: &[raw::c_char]| {
+            ffi::CStr::from_ptr(chars.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        };
+        Some(DeviceFault {
+            description: text(&info.description),
+            addresses: addresses[..counts.address_info_count as usize]
+                .iter()
+                .map(|address| (address.address_type, address.reported_address))
+                .collect(),
+            vendor: vendor[..counts.vendor_info_count as usize]
+                .iter()
+                .map(|vendor| {
+                    (
+                        text(&vendor.description),
+                        vendor.vendor_fault_code,
+                        vendor.vendor_fault_data,
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    unsafe fn queue_checkpoints(&self, queue: vk::Queue) -> Vec<Checkpoint> {
+        let checkpoints = match &self.checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => return vec![],
+        };
+        let mut data =
+            vec![vk::CheckpointDataNV::default(); checkpoints.get_queue_checkpoint_data_len(queue)];
+        checkpoints.get_queue_checkpoint_data(queue, &mut data);
+        data.iter()
+            .map(|checkpoint| Checkpoint {
+                stage: checkpoint.stage,
+                marker: checkpoint.p_checkpoint_marker as u64,
+            })
+            .collect()
+    }
+
+    /// Names `object` in validation messages and debuggers,
+    /// does nothing unless the instance was created with `debug`.
+    pub(crate) fn set_object_name<T: vk::Handle>(&self, object: T, name: &str) {
+        if let Some(debug) = &self.instance.debug {
+            let name = ffi::CString::new(name).unwrap_or_default();
+            let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(T::TYPE)
+                .object_handle(object.as_raw())
+                .object_name(&name);
+            unsafe {
+                let _ = debug
+                    .loader
+                    .set_debug_utils_object_name(self.handle.handle(), &info);
+            }
+        }
+    }
 }
 
 impl Device {
     pub fn raw(&self) -> &ash::Device {
         &self.shared.handle
     }
+
+    /// Whether the device was lost, every call that can report it fails with `DeviceError::Lost`
+    /// from then on.
+    ///
+    /// To recover, drop everything created from this device, including its queues, surfaces'
+    /// swapchains and the `Device` itself, then request a new device from the adapter and
+    /// reconfigure the surfaces. The instance and adapter stay valid.
+    pub fn is_lost(&self) -> bool {
+        self.shared.lost.load(Ordering::Acquire)
+    }
+
+    /// Destroys dropped resources whose submissions have finished, also done on every
+    /// `Queue::submit`. A lost device keeps them until it is dropped.
+    pub fn maintain(&self) -> Result<(), DeviceError> {
+        self.shared.check_lost()?;
+        self.shared.maintain();
+        Ok(())
+    }
+
+    /// Called once, on the thread that first observes the device loss.
+    pub fn on_device_lost(&self, callback: impl Fn(&DeviceLostInfo) + Send + Sync + 'static) {
+        *self.shared.lost_callback.lock() = Some(Box::new(callback));
+    }
+
+    /// Queue families are ignored for `SharingMode::Exclusive`.
+    pub(crate) fn validate_sharing(
+        &self,
+        sharing: vt::SharingMode,
+        queue_families: &[u32],
+    ) -> Result<(), DeviceError> {
+        check_sharing(
+            sharing,
+            queue_families,
+            self.shared.adapter.queue_families.len() as u32,
+        )
+    }
+}
+
+/// Concurrent sharing needs at least 2 distinct families out of `family_count`.
+fn check_sharing(
+    sharing: vt::SharingMode,
+    queue_families: &[u32],
+    family_count: u32,
+) -> Result<(), DeviceError> {
+    if let vt::SharingMode::Exclusive = sharing {
+        return Ok(());
+    }
+
+    let valid = queue_families.len() >= 2
+        && queue_families.iter().all(|&family| family < family_count)
+        && queue_families
+            .iter()
+            .enumerate()
+            .all(|(i, family)| !queue_families[..i].contains(family));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(DeviceError::InvalidQueueFamilies(queue_families.to_vec()))
+    }
 }
 
 impl Adapter {
@@ -47,6 +267,7 @@ impl Adapter {
             mut extensions,
             features,
             queue_families,
+            allocator_config,
         } = info;
 
         let instance = self.instance.clone();
@@ -104,17 +325,31 @@ impl Adapter {
         let mut synchronization2 =
             vk::PhysicalDeviceSynchronization2Features::builder().synchronization2(true);
 
-        let mut vulkan12features =
-            vk::PhysicalDeviceVulkan12Features::builder().timeline_semaphore(true);
+        let mut vulkan12features = vk::PhysicalDeviceVulkan12Features::builder()
+            .timeline_semaphore(true)
+            .host_query_reset(self.host_query_reset);
 
         let mut vulkan_dynamic_rendering =
             vk::PhysicalDeviceDynamicRenderingFeatures::builder().dynamic_rendering(true);
 
-        let device_info = device_info
+        let mut fault_features = vk::PhysicalDeviceFaultFeaturesEXT::builder().device_fault(true);
+
+        let mut priority_features =
+            vk::PhysicalDeviceMemoryPriorityFeaturesEXT::builder().memory_priority(true);
+
+        let mut device_info = device_info
             .push_next(&mut vulkan_dynamic_rendering)
             .push_next(&mut vulkan12features)
-            .push_next(&mut synchronization2)
-            .build();
+            .push_next(&mut synchronization2);
+
+        if extensions.vk_ext_device_fault {
+            device_info = device_info.push_next(&mut fault_features);
+        }
+        if extensions.vk_ext_memory_priority {
+            device_info = device_info.push_next(&mut priority_features);
+        }
+
+        let device_info = device_info.build();
 
         let vk_handle_device = unsafe {
             instance
@@ -123,10 +358,49 @@ impl Adapter {
                 .map_err(|err| DeviceError::Other(err))?
         };
 
+        let fault = extensions.vk_ext_device_fault.then(|| {
+            vk::ExtDeviceFaultFn::load(|name| unsafe {
+                mem::transmute(
+                    instance
+                        .handle
+                        .get_device_proc_addr(vk_handle_device.handle(), name.as_ptr()),
+                )
+            })
+        });
+        let checkpoints = extensions
+            .vk_nv_device_diagnostic_checkpoints
+            .then(|| nv::DeviceDiagnosticCheckpoints::new(&instance.handle, &vk_handle_device));
+
+        let allocator = {
+            let properties = unsafe {
+                gpu_alloc_ash::device_properties(
+                    &self.instance.handle,
+                    instance.version.to_vulkan(),
+                    self.handle,
+                )
+                .unwrap()
+            };
+
+            Allocator::new(
+                allocator_config,
+                properties,
+                self.properties.limits.buffer_image_granularity,
+            )
+        };
+
         let vk_device = Arc::new(DeviceShared {
             handle: vk_handle_device,
             adapter: Arc::new(self.clone()),
             instance: instance.clone(),
+            lost: AtomicBool::new(false),
+            lost_callback: Mutex::new(None),
+            last_submitted: Mutex::new(vec![]),
+            fault,
+            checkpoints,
+            allocator: Mutex::new(allocator),
+            memory_budget: extensions.vk_ext_memory_budget,
+            memory_priority: extensions.vk_ext_memory_priority,
+            destroy: Mutex::new(DestroyQueue::default()),
         });
 
         let queue_iter = {
@@ -139,6 +413,7 @@ impl Adapter {
                     let queue_id = vk_queue.as_raw();
                     let queue = Queue {
                         handle: Mutex::new(vk_queue),
+                        timeline: device.add_queue_timeline(),
                         device: device.clone(),
                         id: queue_id,
                         family,
@@ -148,24 +423,9 @@ impl Adapter {
                 })
         };
 
-        let allocator = {
-            let config = gpu_alloc::Config::i_am_prototyping();
-            let properties = unsafe {
-                gpu_alloc_ash::device_properties(
-                    &self.instance.handle,
-                    instance.version.to_vulkan(),
-                    self.handle,
-                )
-                .unwrap()
-            };
-
-            gpu_alloc::GpuAllocator::new(config, properties)
-        };
-
         let device = Device {
             shared: vk_device.clone(),
-            allocator: Mutex::new(allocator),
-            command_encoders: Mutex::new(HashMap::new()),
+            command_encoders: Arc::default(),
         };
 
         Ok((device, queue_iter))
@@ -182,6 +442,7 @@ pub struct DeviceCreateInfo<'q> {
     pub extensions: Extensions,
     pub features: Features,
     pub queue_families: Vec<QueueCreateInfo<'q>>,
+    pub allocator_config: AllocatorConfig,
 }
 
 impl<'q> Default for DeviceCreateInfo<'q> {
@@ -190,19 +451,73 @@ impl<'q> Default for DeviceCreateInfo<'q> {
             extensions: Extensions::none(),
             features: Features::none(),
             queue_families: vec![],
+            allocator_config: AllocatorConfig::default(),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum DeviceError {
+    /// The device was lost, see `Device::is_lost`.
+    Lost,
     Other(vk::Result),
+    /// No memory for a buffer or texture, see `Device::memory_report`.
+    Allocation(AllocationError),
+    /// Host access to a buffer's memory failed.
+    Map(MapError),
+    /// Specialization constants were passed to a WGSL module, naga can't compile WGSL
+    /// `override` declarations yet.
+    WgslSpecialization,
+    /// Buffers need at least one byte.
+    ZeroSizedBuffer,
+    /// Initial contents can't be written to a buffer in this location, it isn't host visible.
+    ContentsNotHostVisible(vt::MemoryLocation),
+    /// The adapter doesn't support a feature the call needs.
+    MissingFeature(&'static str),
+    /// A write of `size` bytes at `offset` doesn't fit into a buffer of `buffer_size` bytes.
+    OutOfBounds {
+        offset: vt::BufferAddress,
+        size: vt::BufferAddress,
+        buffer_size: vt::BufferAddress,
+    },
+    /// `SharingMode::Concurrent` resource created without at least two valid, distinct queue families.
+    InvalidQueueFamilies(Vec<u32>),
 }
 
 impl Display for DeviceError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "todo!")?;
-        Ok(())
+        match self {
+            DeviceError::Lost => write!(f, "device lost"),
+            DeviceError::Other(result) => write!(f, "{}", result),
+            DeviceError::Allocation(err) => write!(f, "{}", err),
+            DeviceError::Map(err) => write!(f, "{}", err),
+            DeviceError::WgslSpecialization => {
+                write!(f, "WGSL modules don't support specialization constants")
+            }
+            DeviceError::ZeroSizedBuffer => write!(f, "buffers can't be empty"),
+            DeviceError::ContentsNotHostVisible(location) => write!(
+                f,
+                "initial contents can't be written to a {:?} buffer, use a staging buffer",
+                location
+            ),
+            DeviceError::MissingFeature(feature) => {
+                write!(f, "the adapter doesn't support {}", feature)
+            }
+            DeviceError::OutOfBounds {
+                offset,
+                size,
+                buffer_size,
+            } => write!(
+                f,
+                "{} bytes at offset {} don't fit into a buffer of {} bytes",
+                size, offset, buffer_size
+            ),
+            DeviceError::InvalidQueueFamilies(families) => write!(
+                f,
+                "concurrent sharing needs at least two distinct, valid queue families, got {:?}",
+                families
+            ),
+        }
     }
 }
 
@@ -211,8 +526,48 @@ impl error::Error for DeviceError {}
 impl Drop for DeviceShared {
     fn drop(&mut self) {
         unsafe {
+            let _ = self.handle.device_wait_idle();
+            self.destroy_pending();
+            self.allocator.get_mut().cleanup(&self.handle);
             self.handle.destroy_device(None);
         }
         info!("Destroyed: Device");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(families: &[u32]) -> bool {
+        matches!(
+            check_sharing(vt::SharingMode::Concurrent, families, 3),
+            Err(DeviceError::InvalidQueueFamilies(list)) if list == families
+        )
+    }
+
+    #[test]
+    fn concurrent_sharing_needs_two_families() {
+        assert!(invalid(&[]));
+        assert!(invalid(&[1]));
+        assert!(check_sharing(vt::SharingMode::Concurrent, &[0, 2], 3).is_ok());
+    }
+
+    #[test]
+    fn concurrent_sharing_rejects_unknown_families() {
+        assert!(invalid(&[0, 3]));
+        assert!(invalid(&[u32::MAX, 1]));
+    }
+
+    #[test]
+    fn concurrent_sharing_rejects_duplicates() {
+        assert!(invalid(&[1, 1]));
+        assert!(invalid(&[0, 1, 0]));
+    }
+
+    #[test]
+    fn exclusive_sharing_ignores_families() {
+        assert!(check_sharing(vt::SharingMode::Exclusive, &[], 3).is_ok());
+        assert!(check_sharing(vt::SharingMode::Exclusive, &[5, 5], 3).is_ok());
+    }
+}
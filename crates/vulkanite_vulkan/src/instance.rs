@@ -1,13 +1,15 @@
-use crate::debug::{VkDebug, VkDebugCallback};
-use crate::types::{Extensions, Layers};
+use crate::debug::{DebugMessage, DebugMessengerInfo, DebugUserData, VkDebug};
+use crate::types::{Extensions, Layers, ValidationFeatures};
 use crate::utils::Version;
 use ash::vk;
+use parking_lot::Mutex;
 use raw_window_handle::HasRawDisplayHandle;
 use std::fmt::{Debug, Display, Formatter};
 use std::os::raw;
+use std::path::Path;
 use std::sync::Arc;
 use std::{error, ffi, ptr};
-use tracing::info;
+use tracing::{info, warn};
 
 pub(crate) struct InstanceShared {
     pub(crate) entry: ash::Entry,
@@ -24,9 +26,14 @@ pub struct Instance {
 }
 
 impl Instance {
+    /// Layers that aren't installed are skipped with a warning.
     pub fn new(info: InstanceCreateInfo) -> Result<Self, InstanceCreationError> {
-        let instance =
-            InstanceShared::init(info).map_err(|err| InstanceCreationError::Other(err))?;
+        if info.validation.gpu_assisted && info.validation.debug_printf {
+            return Err(InstanceCreationError::ConflictingValidationFeatures);
+        }
+
+        let entry = load_entry(info.library_path)?;
+        let instance = InstanceShared::init(entry, info).map_err(InstanceCreationError::Other)?;
 
         Ok(Self {
             shared: Arc::new(instance),
@@ -36,34 +43,116 @@ impl Instance {
     pub fn handle(&self) -> &ash::Instance {
         &self.shared.handle
     }
+
+    /// Names of the installed instance layers, `library_path` is the same as
+    /// `InstanceCreateInfo::library_path`.
+    pub fn available_layers(
+        library_path: Option<&Path>,
+    ) -> Result<Vec<String>, InstanceCreationError> {
+        let entry = load_entry(library_path)?;
+        let layers = available_layers(&entry).map_err(InstanceCreationError::Other)?;
+        Ok(layers
+            .into_iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Names of the instance extensions provided by the implementation, or by `layer`,
+    /// see `available_layers`.
+    pub fn available_extensions(
+        library_path: Option<&Path>,
+        layer: Option<&str>,
+    ) -> Result<Vec<String>, InstanceCreationError> {
+        let entry = load_entry(library_path)?;
+        let layer = layer
+            .map(|name| {
+                ffi::CString::new(name)
+                    .map_err(|_| InstanceCreationError::InvalidLayerName(name.to_string()))
+            })
+            .transpose()?;
+        let extensions =
+            available_extensions(&entry, layer.as_deref()).map_err(InstanceCreationError::Other)?;
+        Ok(extensions
+            .into_iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Whether the Khronos validation layer is enabled and reports to the debug messenger.
+    /// Tests relying on `validation_messages` should skip themselves when it isn't.
+    pub fn validation_enabled(&self) -> bool {
+        self.shared.debug.is_some() && self.shared.layers.khronos_validation
+    }
+
+    /// Validation messages collected so far, needs
+    /// `DebugMessengerInfo::collect_validation_messages`.
+    pub fn validation_messages(&self) -> Vec<DebugMessage<'static>> {
+        match self.collected_messages() {
+            Some(messages) => messages.lock().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Same as `validation_messages`, but clears them.
+    pub fn drain_validation_messages(&self) -> Vec<DebugMessage<'static>> {
+        match self.collected_messages() {
+            Some(messages) => std::mem::take(&mut *messages.lock()),
+            None => Vec::new(),
+        }
+    }
+
+    fn collected_messages(&self) -> Option<&Mutex<Vec<DebugMessage<'static>>>> {
+        self.shared
+            .debug
+            .as_ref()
+            .and_then(|debug| debug.user_data.messages.as_ref())
+    }
 }
 
 impl InstanceShared {
-    pub fn init(info: InstanceCreateInfo) -> Result<Self, vk::Result> {
+    pub fn init(entry: ash::Entry, info: InstanceCreateInfo) -> Result<Self, vk::Result> {
         let InstanceCreateInfo {
             application_name,
             application_version,
             engine_name,
             engine_version,
-            layers,
+            mut layers,
             mut extensions,
             vulkan_version,
             render,
             window,
             debug,
+            debug_messenger,
+            validation,
+            library_path: _,
         } = info;
 
-        #[cfg(not(target_os = "macos"))]
-        let entry = ash::Entry::linked();
-        #[cfg(target_os = "macos")]
-        let entry = ash_molten::load();
-
         let app_name_c = application_name.map(|name| ffi::CString::new(name.as_bytes()).unwrap());
 
         let engine_name_c = engine_name.map(|name| ffi::CString::new(name).unwrap());
 
+        for layer in layers.retain_available(&available_layers(&entry)?) {
+            warn!("Layer {} isn't installed, skipping it", layer);
+        }
         let layer_names = Vec::<ffi::CString>::from(layers);
 
+        let validation_layer = ffi::CString::new("VK_LAYER_KHRONOS_validation").unwrap();
+        let validation = if validation.is_empty() {
+            validation
+        } else if !layers.khronos_validation {
+            warn!("Validation features need the Khronos validation layer, skipping them");
+            ValidationFeatures::default()
+        } else if !available_extensions(&entry, Some(&validation_layer))?
+            .iter()
+            .any(|name| name.to_bytes() == b"VK_EXT_validation_features")
+        {
+            warn!("VK_EXT_validation_features isn't supported, skipping validation features");
+            ValidationFeatures::default()
+        } else {
+            extensions.vk_ext_validation_features = true;
+            validation
+        };
+
         // some defaults
         extensions.vk_khr_get_physical_device_properties2 = true;
 
@@ -137,15 +226,46 @@ impl InstanceShared {
             ..Default::default()
         };
 
-        let instance_info = vk::InstanceCreateInfo::builder()
+        let mut enabled_validation = vec![];
+        if validation.gpu_assisted {
+            enabled_validation.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+        }
+        if validation.best_practices {
+            enabled_validation.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        if validation.synchronization {
+            enabled_validation.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if validation.debug_printf {
+            enabled_validation.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+        }
+        let mut validation_features =
+            vk::ValidationFeaturesEXT::builder().enabled_validation_features(&enabled_validation);
+
+        let mut debug_user_data = DebugUserData::new(&debug_messenger);
+        let mut debug_info = VkDebug::create_info(&debug_messenger, &mut debug_user_data);
+
+        let mut instance_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_layer_names(&layer_pointers)
             .enabled_extension_names(&extension_pointers);
 
+        if !validation.is_empty() {
+            instance_info = instance_info.push_next(&mut validation_features);
+        }
+        if debug {
+            instance_info = instance_info.push_next(&mut debug_info);
+        }
+
         let handle = unsafe { entry.create_instance(&instance_info, None)? };
 
         let debug = if debug {
-            Some(VkDebug::init(&entry, &handle, VkDebugCallback::default())?)
+            Some(VkDebug::init(
+                &entry,
+                &handle,
+                &debug_messenger,
+                debug_user_data,
+            )?)
         } else {
             None
         };
@@ -170,6 +290,51 @@ impl InstanceShared {
     }
 }
 
+#[cfg(all(feature = "loaded", not(target_os = "macos")))]
+fn load_entry(library_path: Option<&Path>) -> Result<ash::Entry, InstanceCreationError> {
+    let entry = unsafe {
+        match library_path {
+            Some(path) => ash::Entry::load_from(path),
+            None => ash::Entry::load(),
+        }
+    };
+    entry.map_err(|err| InstanceCreationError::LoaderNotFound(err.to_string()))
+}
+
+#[cfg(all(not(feature = "loaded"), not(target_os = "macos")))]
+fn load_entry(library_path: Option<&Path>) -> Result<ash::Entry, InstanceCreationError> {
+    if library_path.is_some() {
+        warn!("library_path needs the `loaded` feature, using the linked loader");
+    }
+    Ok(ash::Entry::linked())
+}
+
+#[cfg(target_os = "macos")]
+fn load_entry(_library_path: Option<&Path>) -> Result<ash::Entry, InstanceCreationError> {
+    Ok(ash_molten::load())
+}
+
+fn available_layers(entry: &ash::Entry) -> Result<Vec<ffi::CString>, vk::Result> {
+    Ok(entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .map(|layer| unsafe { ffi::CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+        .collect())
+}
+
+fn available_extensions(
+    entry: &ash::Entry,
+    layer: Option<&ffi::CStr>,
+) -> Result<Vec<ffi::CString>, vk::Result> {
+    Ok(entry
+        .enumerate_instance_extension_properties(layer)?
+        .iter()
+        .map(|extension| {
+            unsafe { ffi::CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned()
+        })
+        .collect())
+}
+
 #[derive(Clone)]
 pub struct InstanceCreateInfo<'a> {
     pub application_name: Option<String>,
@@ -182,6 +347,13 @@ pub struct InstanceCreateInfo<'a> {
     pub render: bool,
     pub window: Option<&'a dyn HasRawDisplayHandle>,
     pub debug: bool,
+    /// Severities, types and receiver of validation messages when `debug` is set.
+    pub debug_messenger: DebugMessengerInfo,
+    /// Only applies when the Khronos validation layer is enabled and installed.
+    pub validation: ValidationFeatures,
+    /// Vulkan loader or ICD library to load instead of the system loader, e.g. lavapipe for
+    /// testing. Needs the `loaded` feature.
+    pub library_path: Option<&'a Path>,
 }
 
 impl Default for InstanceCreateInfo<'_> {
@@ -197,19 +369,39 @@ impl Default for InstanceCreateInfo<'_> {
             render: true,
             window: None,
             debug: true,
+            debug_messenger: DebugMessengerInfo::default(),
+            validation: ValidationFeatures::default(),
+            library_path: None,
         }
     }
 }
 
 #[derive(Debug)]
 pub enum InstanceCreationError {
+    /// `ValidationFeatures::gpu_assisted` and `debug_printf` were both set.
+    ConflictingValidationFeatures,
+    /// Layer name containing a nul byte.
+    InvalidLayerName(String),
+    /// The Vulkan library couldn't be loaded, only with the `loaded` feature.
+    LoaderNotFound(String),
     Other(vk::Result),
 }
 
 impl Display for InstanceCreationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "todo!")?;
-        Ok(())
+        match self {
+            InstanceCreationError::ConflictingValidationFeatures => write!(
+                f,
+                "gpu assisted validation and debug printf can't be enabled together"
+            ),
+            InstanceCreationError::InvalidLayerName(name) => {
+                write!(f, "invalid layer name {:?}", name)
+            }
+            InstanceCreationError::LoaderNotFound(err) => {
+                write!(f, "failed to load the Vulkan library: {}", err)
+            }
+            InstanceCreationError::Other(err) => write!(f, "{}", err),
+        }
     }
 }
 
@@ -218,7 +410,10 @@ impl error::Error for InstanceCreationError {}
 impl Drop for InstanceShared {
     fn drop(&mut self) {
         unsafe {
-            if let Some(debug) = self.debug.take() {
+            // the user data is also used by the messenger chained into the instance create info,
+            // it has to outlive the instance
+            let debug = self.debug.take();
+            if let Some(debug) = &debug {
                 debug
                     .loader
                     .destroy_debug_utils_messenger(debug.messenger, None);
@@ -227,6 +422,7 @@ impl Drop for InstanceShared {
             }
             self.handle.destroy_instance(None);
             info!("Destroyed: Instance");
+            drop(debug);
         }
     }
 }
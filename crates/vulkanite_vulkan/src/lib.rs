@@ -1,43 +1,80 @@
 extern crate core;
 
+#[cfg(not(any(feature = "linked", feature = "loaded")))]
+compile_error!("either the `linked` or the `loaded` feature has to be enabled");
+
 mod adapter;
 mod buffer;
+mod bundle;
 mod color;
 mod command;
 mod conv;
 mod debug;
+mod deferred;
 mod device;
 mod error;
 mod instance;
+mod memory;
 mod pipeline;
+mod profiler;
+mod query;
 mod queue;
 mod shader;
+mod staging;
 mod surface;
 mod sync;
 mod texture;
+mod track;
 mod types;
 mod utils;
 
 pub mod vn {
     pub use crate::adapter::Adapter;
     pub use crate::buffer::{Buffer, BufferInitInfo};
+    pub use crate::bundle::RenderBundleEncoder;
     pub use crate::color::Color;
-    pub use crate::command::{AccessFlags, CommandEncoder, CommandEncoderInfo, StageFlags};
-    pub use crate::device::{Device, DeviceCreateInfo, DeviceError};
+    pub use crate::command::{
+        AccessFlags, BarrierBatch, CommandBuffer, CommandEncoder, CommandEncoderInfo, RenderBundle,
+        StageFlags,
+    };
+    pub use crate::debug::{
+        DebugCallback, DebugMessage, DebugMessageTypes, DebugMessengerInfo, DebugObject,
+        DebugSeverities,
+    };
+    pub use crate::device::{
+        Checkpoint, Device, DeviceCreateInfo, DeviceError, DeviceFault, DeviceLostCallback,
+        DeviceLostInfo,
+    };
     pub use crate::instance::{Instance, InstanceCreateInfo, InstanceCreationError};
+    pub use crate::memory::{AllocationError, AllocatorConfig, HeapReport, MapError, MemoryReport};
     pub use crate::pipeline::{
-        ComputePipelineInfo, FragmentState, PipelineLayoutInfo, RasterPipelineInfo, ShaderStage,
+        ComputePipeline, ComputePipelineInfo, FragmentState, PipelineCache, PipelineLayoutInfo,
+        RasterPipeline, RasterPipelineInfo, ShaderStage,
     };
+    pub use crate::profiler::{CpuScope, GpuProfiler, GpuTiming, ProfileScope, ProfilerInfo};
+    pub use crate::query::QuerySet;
     pub use crate::queue::{Queue, QueueCreateInfo, QueueFamily};
-    pub use crate::shader::{ShaderKind, ShaderSource};
+    pub use crate::shader::{
+        BindingInfo, BindingMap, BoundsCheckPolicies, BoundsCheckPolicy, ResourceBinding,
+        ShaderCapabilities, ShaderError, ShaderKind, ShaderModule, ShaderSource,
+        ShaderValidationError, WgslShaderCompileInfo,
+    };
+    pub use crate::staging::{FrameSignal, StagingBelt};
+    pub use crate::surface::Frame;
     pub use crate::surface::{Surface, SurfaceConfig, SurfaceError, Swapchain};
     pub use crate::sync::{BinarySemaphore, Fence, TimelineSemaphore};
-    pub use crate::texture::TextureView;
+    pub use crate::texture::{Texture, TextureView};
     pub use crate::types::*;
     pub use crate::utils::Version;
+    pub use vulkanite_derive::VertexLayout;
     pub use vulkanite_types::*;
 }
 
+#[doc(hidden)]
+pub mod __private {
+    pub use bytemuck;
+}
+
 pub mod raw {
     pub use ash::*;
 }
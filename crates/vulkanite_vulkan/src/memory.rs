@@ -0,0 +1,685 @@
+use crate::device::Device;
+use ash::vk;
+use gpu_alloc::{AllocationFlags, DeviceMapError, MappedMemoryRange, MemoryDevice, OutOfMemory};
+use gpu_alloc_ash::AshMemoryDevice;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ptr::NonNull;
+
+/// Sizes the allocator picks its memory objects by, see `DeviceCreateInfo::allocator_config`.
+/// The default suits desktop GPUs, devices with small heaps need smaller values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorConfig {
+    /// Requests from this size on get a memory object of their own.
+    pub dedicated_threshold: u64,
+    /// Same for images whose driver prefers a dedicated allocation.
+    pub preferred_dedicated_threshold: u64,
+    /// Same for transient requests, e.g. `MemoryLocation::Transient`.
+    pub transient_dedicated_threshold: u64,
+    /// Size of the first memory object of the free-list allocator.
+    pub starting_free_list_chunk: u64,
+    /// Upper limit for the memory objects of the free-list allocator.
+    pub final_free_list_chunk: u64,
+    /// Smallest block of the buddy allocator.
+    pub minimal_buddy_size: u64,
+    /// Size of the memory objects of the buddy allocator.
+    pub initial_buddy_dedicated_size: u64,
+}
+
+impl Default for AllocatorConfig {
+    fn default() -> Self {
+        let config = gpu_alloc::Config::i_am_prototyping();
+        Self {
+            dedicated_threshold: config.dedicated_threshold,
+            preferred_dedicated_threshold: config.preferred_dedicated_threshold,
+            transient_dedicated_threshold: config.transient_dedicated_threshold,
+            starting_free_list_chunk: config.starting_free_list_chunk,
+            final_free_list_chunk: config.final_free_list_chunk,
+            minimal_buddy_size: config.minimal_buddy_size,
+            initial_buddy_dedicated_size: config.initial_buddy_dedicated_size,
+        }
+    }
+}
+
+impl From<AllocatorConfig> for gpu_alloc::Config {
+    fn from(config: AllocatorConfig) -> Self {
+        Self {
+            dedicated_threshold: config.dedicated_threshold,
+            preferred_dedicated_threshold: config.preferred_dedicated_threshold,
+            transient_dedicated_threshold: config.transient_dedicated_threshold,
+            starting_free_list_chunk: config.starting_free_list_chunk,
+            final_free_list_chunk: config.final_free_list_chunk,
+            minimal_buddy_size: config.minimal_buddy_size,
+            initial_buddy_dedicated_size: config.initial_buddy_dedicated_size,
+        }
+    }
+}
+
+/// Reason a buffer or texture got no memory, see `Device::memory_report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationError {
+    OutOfDeviceMemory,
+    OutOfHostMemory,
+    /// None of the memory types the resource supports fits its `MemoryLocation`.
+    NoCompatibleMemoryTypes,
+    /// The device's limit on memory objects was reached, larger values in `AllocatorConfig`
+    /// need fewer of them.
+    TooManyObjects,
+}
+
+impl From<gpu_alloc::AllocationError> for AllocationError {
+    fn from(err: gpu_alloc::AllocationError) -> Self {
+        match err {
+            gpu_alloc::AllocationError::OutOfDeviceMemory => Self::OutOfDeviceMemory,
+            gpu_alloc::AllocationError::OutOfHostMemory => Self::OutOfHostMemory,
+            gpu_alloc::AllocationError::NoCompatibleMemoryTypes => Self::NoCompatibleMemoryTypes,
+            gpu_alloc::AllocationError::TooManyObjects => Self::TooManyObjects,
+        }
+    }
+}
+
+impl Display for AllocationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocationError::OutOfDeviceMemory => write!(f, "out of device memory"),
+            AllocationError::OutOfHostMemory => write!(f, "out of host memory"),
+            AllocationError::NoCompatibleMemoryTypes => {
+                write!(
+                    f,
+                    "no memory type is compatible with the requested location"
+                )
+            }
+            AllocationError::TooManyObjects => write!(f, "too many memory objects"),
+        }
+    }
+}
+
+/// Reason a buffer's memory couldn't be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    OutOfDeviceMemory,
+    OutOfHostMemory,
+    /// The memory isn't host visible.
+    NonHostVisible,
+    /// The implementation couldn't map it, e.g. for lack of address space.
+    MapFailed,
+    AlreadyMapped,
+}
+
+impl From<gpu_alloc::MapError> for MapError {
+    fn from(err: gpu_alloc::MapError) -> Self {
+        match err {
+            gpu_alloc::MapError::OutOfDeviceMemory => Self::OutOfDeviceMemory,
+            gpu_alloc::MapError::OutOfHostMemory => Self::OutOfHostMemory,
+            gpu_alloc::MapError::NonHostVisible => Self::NonHostVisible,
+            gpu_alloc::MapError::MapFailed => Self::MapFailed,
+            gpu_alloc::MapError::AlreadyMapped => Self::AlreadyMapped,
+        }
+    }
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapError::OutOfDeviceMemory => write!(f, "out of device memory"),
+            MapError::OutOfHostMemory => write!(f, "out of host memory"),
+            MapError::NonHostVisible => write!(f, "memory isn't host visible"),
+            MapError::MapFailed => write!(f, "mapping memory failed"),
+            MapError::AlreadyMapped => write!(f, "memory is already mapped"),
+        }
+    }
+}
+
+/// `gpu_alloc` allocator that keeps track of the blocks and memory objects of every heap.
+pub(crate) struct Allocator {
+    inner: gpu_alloc::GpuAllocator<vk::DeviceMemory>,
+    /// Bits of the memory types with `LAZILY_ALLOCATED`.
+    lazily_allocated: u32,
+    /// `AllocatorConfig::preferred_dedicated_threshold`, images from this size on get a memory
+    /// object of their own.
+    dedicated_image_threshold: u64,
+    padding: Padding,
+    usage: MemoryUsage,
+}
+
+/// Resource a dedicated memory object is bound to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum MemoryOwner {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+/// Limits every request is padded to, on top of the resource's own alignment.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Padding {
+    /// `bufferImageGranularity`, keeps buffers and optimal tiling images that share a memory
+    /// object out of each other's pages.
+    pub(crate) granularity: u64,
+    /// `nonCoherentAtomSize`, keeps flushes of host visible blocks from touching their neighbours.
+    pub(crate) non_coherent_atom_size: u64,
+}
+
+impl Padding {
+    /// Size and alignment mask of a request for a resource of `size` bytes that needs
+    /// `alignment`, every value has to be a power of two.
+    pub(crate) fn pad(&self, size: u64, alignment: u64, host_access: bool) -> (u64, u64) {
+        let mut granule = self.granularity.max(1);
+        if host_access {
+            granule = granule.max(self.non_coherent_atom_size);
+        }
+        let alignment = alignment.max(granule);
+        (align_up(size, granule), alignment - 1)
+    }
+
+    /// `request` padded by `pad`, unless it gets a memory object of its own, which has to be
+    /// exactly as large as the resource it is dedicated to.
+    pub(crate) fn pad_request(
+        &self,
+        request: gpu_alloc::Request,
+        dedicated: bool,
+    ) -> gpu_alloc::Request {
+        if dedicated {
+            return request;
+        }
+        let (size, align_mask) = self.pad(
+            request.size,
+            request.align_mask + 1,
+            request.usage.contains(gpu_alloc::UsageFlags::HOST_ACCESS),
+        );
+        gpu_alloc::Request {
+            size,
+            align_mask,
+            ..request
+        }
+    }
+}
+
+/// Rounds `value` up to a multiple of `alignment`, a power of two.
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    debug_assert!(alignment.is_power_of_two());
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HeapUsage {
+    /// Blocks handed out to buffers and textures.
+    allocated: u64,
+    blocks: u32,
+    /// Memory objects the blocks are sub-allocated from.
+    memory: u64,
+    memory_objects: u32,
+}
+
+#[derive(Debug, Default)]
+struct MemoryUsage {
+    /// Heap of every memory type.
+    type_heaps: Vec<u32>,
+    heaps: Vec<HeapUsage>,
+    /// Heap and size of every live memory object.
+    objects: HashMap<vk::DeviceMemory, (usize, u64)>,
+}
+
+impl MemoryUsage {
+    fn heap(&mut self, memory_type: u32) -> &mut HeapUsage {
+        &mut self.heaps[self.type_heaps[memory_type as usize] as usize]
+    }
+
+    fn allocated(&mut self, memory: vk::DeviceMemory, memory_type: u32, size: u64) {
+        let heap = self.type_heaps[memory_type as usize] as usize;
+        self.heaps[heap].memory += size;
+        self.heaps[heap].memory_objects += 1;
+        self.objects.insert(memory, (heap, size));
+    }
+
+    fn deallocated(&mut self, memory: vk::DeviceMemory) {
+        if let Some((heap, size)) = self.objects.remove(&memory) {
+            self.heaps[heap].memory -= size;
+            self.heaps[heap].memory_objects -= 1;
+        }
+    }
+}
+
+impl Allocator {
+    pub(crate) fn new(
+        config: AllocatorConfig,
+        properties: gpu_alloc::DeviceProperties<'_>,
+        granularity: u64,
+    ) -> Self {
+        let padding = Padding {
+            granularity,
+            non_coherent_atom_size: properties.non_coherent_atom_size,
+        };
+        let usage = MemoryUsage {
+            type_heaps: properties.memory_types.iter().map(|ty| ty.heap).collect(),
+            heaps: vec![HeapUsage::default(); properties.memory_heaps.len()],
+            objects: HashMap::new(),
+        };
+        let lazily_allocated = properties
+            .memory_types
+            .iter()
+            .enumerate()
+            .filter(|(_, ty)| {
+                ty.props
+                    .contains(gpu_alloc::MemoryPropertyFlags::LAZILY_ALLOCATED)
+            })
+            .fold(0, |bits, (index, _)| bits | 1 << index);
+        Self {
+            inner: gpu_alloc::GpuAllocator::new(config.into(), properties),
+            lazily_allocated,
+            dedicated_image_threshold: config.preferred_dedicated_threshold,
+            padding,
+            usage,
+        }
+    }
+
+    /// Request for a resource with `requirements`, `alloc_for` pads it.
+    pub(crate) fn request(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        usage: gpu_alloc::UsageFlags,
+    ) -> gpu_alloc::Request {
+        gpu_alloc::Request {
+            size: requirements.size,
+            align_mask: requirements.alignment - 1,
+            usage,
+            memory_types: requirements.memory_type_bits,
+        }
+    }
+
+    /// Narrows `memory_types` to the lazily allocated ones, if it has any.
+    pub(crate) fn prefer_lazily_allocated(&self, memory_types: u32) -> u32 {
+        match memory_types & self.lazily_allocated {
+            0 => memory_types,
+            lazy => lazy,
+        }
+    }
+
+    /// Gives the block a memory object bound to `owner` alone, with `priority` when it is
+    /// set. Used when the driver asks for it with `dedicated`, for a priority, which applies
+    /// to a whole memory object, and for images from `dedicated_image_threshold` on.
+    pub(crate) unsafe fn alloc_for(
+        &mut self,
+        device: &ash::Device,
+        request: gpu_alloc::Request,
+        owner: MemoryOwner,
+        dedicated: bool,
+        priority: Option<f32>,
+    ) -> Result<gpu_alloc::MemoryBlock<vk::DeviceMemory>, AllocationError> {
+        let large_image = matches!(owner, MemoryOwner::Image(_))
+            && request.size >= self.dedicated_image_threshold;
+        let dedicated = dedicated || priority.is_some() || large_image;
+        let request = self.padding.pad_request(request, dedicated);
+
+        let block = if dedicated {
+            self.inner.alloc_with_dedicated(
+                &TrackedMemoryDevice::new(
+                    &DedicatedMemoryDevice {
+                        device,
+                        owner,
+                        priority,
+                    },
+                    &mut self.usage,
+                ),
+                request,
+                gpu_alloc::Dedicated::Required,
+            )?
+        } else {
+            self.inner.alloc(
+                &TrackedMemoryDevice::new(AshMemoryDevice::wrap(device), &mut self.usage),
+                request,
+            )?
+        };
+        self.track(&block);
+        Ok(block)
+    }
+
+    fn track(&mut self, block: &gpu_alloc::MemoryBlock<vk::DeviceMemory>) {
+        let heap = self.usage.heap(block.memory_type());
+        heap.allocated += block.size();
+        heap.blocks += 1;
+    }
+
+    pub(crate) unsafe fn dealloc(
+        &mut self,
+        device: &ash::Device,
+        block: gpu_alloc::MemoryBlock<vk::DeviceMemory>,
+    ) {
+        let heap = self.usage.heap(block.memory_type());
+        heap.allocated -= block.size();
+        heap.blocks -= 1;
+        self.inner.dealloc(
+            &TrackedMemoryDevice::new(AshMemoryDevice::wrap(device), &mut self.usage),
+            block,
+        );
+    }
+
+    pub(crate) unsafe fn cleanup(&mut self, device: &ash::Device) {
+        self.inner.cleanup(&TrackedMemoryDevice::new(
+            AshMemoryDevice::wrap(device),
+            &mut self.usage,
+        ));
+    }
+}
+
+/// Counts the memory objects `device` allocates and frees into `MemoryUsage`.
+struct TrackedMemoryDevice<'a, D> {
+    device: &'a D,
+    usage: RefCell<&'a mut MemoryUsage>,
+}
+
+impl<'a, D> TrackedMemoryDevice<'a, D> {
+    fn new(device: &'a D, usage: &'a mut MemoryUsage) -> Self {
+        Self {
+            device,
+            usage: RefCell::new(usage),
+        }
+    }
+}
+
+impl<D: MemoryDevice<vk::DeviceMemory>> MemoryDevice<vk::DeviceMemory>
+    for TrackedMemoryDevice<'_, D>
+{
+    unsafe fn allocate_memory(
+        &self,
+        size: u64,
+        memory_type: u32,
+        flags: AllocationFlags,
+    ) -> Result<vk::DeviceMemory, OutOfMemory> {
+        let memory = self.device.allocate_memory(size, memory_type, flags)?;
+        self.usage.borrow_mut().allocated(memory, memory_type, size);
+        Ok(memory)
+    }
+
+    unsafe fn deallocate_memory(&self, memory: vk::DeviceMemory) {
+        self.usage.borrow_mut().deallocated(memory);
+        self.device.deallocate_memory(memory)
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut vk::DeviceMemory,
+        offset: u64,
+        size: u64,
+    ) -> Result<NonNull<u8>, DeviceMapError> {
+        self.device.map_memory(memory, offset, size)
+    }
+
+    unsafe fn unmap_memory(&self, memory: &mut vk::DeviceMemory) {
+        self.device.unmap_memory(memory)
+    }
+
+    unsafe fn invalidate_memory_ranges(
+        &self,
+        ranges: &[MappedMemoryRange<'_, vk::DeviceMemory>],
+    ) -> Result<(), OutOfMemory> {
+        self.device.invalidate_memory_ranges(ranges)
+    }
+
+    unsafe fn flush_memory_ranges(
+        &self,
+        ranges: &[MappedMemoryRange<'_, vk::DeviceMemory>],
+    ) -> Result<(), OutOfMemory> {
+        self.device.flush_memory_ranges(ranges)
+    }
+}
+
+/// Allocates memory objects dedicated to one resource, only used for `Dedicated::Required`
+/// where every memory object belongs to a single block.
+struct DedicatedMemoryDevice<'a> {
+    device: &'a ash::Device,
+    owner: MemoryOwner,
+    priority: Option<f32>,
+}
+
+impl MemoryDevice<vk::DeviceMemory> for DedicatedMemoryDevice<'_> {
+    unsafe fn allocate_memory(
+        &self,
+        size: u64,
+        memory_type: u32,
+        flags: AllocationFlags,
+    ) -> Result<vk::DeviceMemory, OutOfMemory> {
+        let mut dedicated_info = match self.owner {
+            MemoryOwner::Buffer(buffer) => {
+                vk::MemoryDedicatedAllocateInfo::builder().buffer(buffer)
+            }
+            MemoryOwner::Image(image) => vk::MemoryDedicatedAllocateInfo::builder().image(image),
+        };
+        let mut priority_info =
+            vk::MemoryPriorityAllocateInfoEXT::builder().priority(self.priority.unwrap_or(0.5));
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::builder().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let mut info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type)
+            .push_next(&mut dedicated_info);
+        if self.priority.is_some() {
+            info = info.push_next(&mut priority_info);
+        }
+        if flags.contains(AllocationFlags::DEVICE_ADDRESS) {
+            info = info.push_next(&mut flags_info);
+        }
+
+        match self.device.allocate_memory(&info, None) {
+            Ok(memory) => Ok(memory),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => Err(OutOfMemory::OutOfDeviceMemory),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => Err(OutOfMemory::OutOfHostMemory),
+            Err(err) => panic!("Unexpected Vulkan error: `{}`", err),
+        }
+    }
+
+    unsafe fn deallocate_memory(&self, memory: vk::DeviceMemory) {
+        AshMemoryDevice::wrap(self.device).deallocate_memory(memory)
+    }
+
+    unsafe fn map_memory(
+        &self,
+        memory: &mut vk::DeviceMemory,
+        offset: u64,
+        size: u64,
+    ) -> Result<NonNull<u8>, DeviceMapError> {
+        AshMemoryDevice::wrap(self.device).map_memory(memory, offset, size)
+    }
+
+    unsafe fn unmap_memory(&self, memory: &mut vk::DeviceMemory) {
+        AshMemoryDevice::wrap(self.device).unmap_memory(memory)
+    }
+
+    unsafe fn invalidate_memory_ranges(
+        &self,
+        ranges: &[MappedMemoryRange<'_, vk::DeviceMemory>],
+    ) -> Result<(), OutOfMemory> {
+        AshMemoryDevice::wrap(self.device).invalidate_memory_ranges(ranges)
+    }
+
+    unsafe fn flush_memory_ranges(
+        &self,
+        ranges: &[MappedMemoryRange<'_, vk::DeviceMemory>],
+    ) -> Result<(), OutOfMemory> {
+        AshMemoryDevice::wrap(self.device).flush_memory_ranges(ranges)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    pub size: u64,
+    pub device_local: bool,
+    /// Bytes in live buffers and textures.
+    pub allocated: u64,
+    /// Live buffers and textures, each is a block sub-allocated from a memory object or given
+    /// one of its own.
+    pub blocks: u32,
+    /// Bytes in the memory objects the allocator got from `vkAllocateMemory`, including the
+    /// parts that aren't handed out to blocks.
+    pub memory: u64,
+    pub memory_objects: u32,
+    /// Bytes the process uses from the heap, including memory the allocator holds on to.
+    /// Needs `Extensions::vk_ext_memory_budget`, like `budget`.
+    pub usage: Option<u64>,
+    /// Bytes the process can use from the heap before allocations are likely to fail,
+    /// changes with the memory use of other processes.
+    pub budget: Option<u64>,
+}
+
+impl HeapReport {
+    /// Share of `memory` that isn't handed out to live blocks, 0 when it all is.
+    pub fn fragmentation(&self) -> f32 {
+        if self.memory == 0 {
+            return 0.0;
+        }
+        self.memory.saturating_sub(self.allocated) as f32 / self.memory as f32
+    }
+
+    pub fn budget_left(&self) -> Option<u64> {
+        Some(self.budget?.saturating_sub(self.usage?))
+    }
+}
+
+impl Device {
+    /// Current memory use of every heap, ordered like the adapter's memory heaps.
+    pub fn memory_report(&self) -> MemoryReport {
+        let instance = &self.shared.instance.handle;
+        let physical_device = self.shared.adapter.handle;
+
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::builder();
+        if self.shared.memory_budget {
+            properties = properties.push_next(&mut budget);
+        }
+        unsafe {
+            instance.get_physical_device_memory_properties2(physical_device, &mut properties);
+        }
+        let properties = properties.memory_properties;
+
+        let allocator = self.shared.allocator.lock();
+        let heaps = properties.memory_heaps[..properties.memory_heap_count as usize]
+            .iter()
+            .zip(&allocator.usage.heaps)
+            .enumerate()
+            .map(|(index, (heap, usage))| HeapReport {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                allocated: usage.allocated,
+                blocks: usage.blocks,
+                memory: usage.memory,
+                memory_objects: usage.memory_objects,
+                usage: self.shared.memory_budget.then(|| budget.heap_usage[index]),
+                budget: self.shared.memory_budget.then(|| budget.heap_budget[index]),
+            })
+            .collect();
+
+        MemoryReport { heaps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PADDING: Padding = Padding {
+        granularity: 1024,
+        non_coherent_atom_size: 64,
+    };
+
+    #[test]
+    fn align_up_rounds_to_multiples() {
+        assert_eq!(align_up(0, 256), 0);
+        assert_eq!(align_up(1, 256), 256);
+        assert_eq!(align_up(256, 256), 256);
+        assert_eq!(align_up(257, 256), 512);
+        assert_eq!(align_up(13, 1), 13);
+    }
+
+    #[test]
+    fn pad_uses_granularity() {
+        assert_eq!(PADDING.pad(100, 16, false), (1024, 1023));
+        assert_eq!(PADDING.pad(1025, 256, false), (2048, 1023));
+        assert_eq!(PADDING.pad(4096, 4096, false), (4096, 4095));
+    }
+
+    #[test]
+    fn pad_host_access_uses_atom_size() {
+        let padding = Padding {
+            granularity: 1,
+            non_coherent_atom_size: 256,
+        };
+        assert_eq!(padding.pad(4, 4, true), (256, 255));
+        assert_eq!(padding.pad(4, 4, false), (4, 3));
+        assert_eq!(padding.pad(300, 1024, true), (512, 1023));
+        // the larger of the two limits wins
+        assert_eq!(PADDING.pad(100, 16, true), (1024, 1023));
+    }
+
+    #[test]
+    fn memory_objects_are_counted_per_heap() {
+        use ash::vk::Handle;
+
+        let mut usage = MemoryUsage {
+            type_heaps: vec![0, 1, 1],
+            heaps: vec![HeapUsage::default(); 2],
+            objects: HashMap::new(),
+        };
+        usage.allocated(vk::DeviceMemory::from_raw(1), 2, 4096);
+        usage.allocated(vk::DeviceMemory::from_raw(2), 1, 1024);
+        usage.allocated(vk::DeviceMemory::from_raw(3), 0, 256);
+        assert_eq!(usage.heaps[1].memory, 5120);
+        assert_eq!(usage.heaps[1].memory_objects, 2);
+
+        usage.deallocated(vk::DeviceMemory::from_raw(1));
+        assert_eq!(usage.heaps[1].memory, 1024);
+        assert_eq!(usage.heaps[1].memory_objects, 1);
+        assert_eq!(usage.heaps[0].memory, 256);
+    }
+
+    #[test]
+    fn fragmentation_is_the_unused_share_of_memory_objects() {
+        let mut report = HeapReport {
+            size: 1 << 30,
+            device_local: true,
+            allocated: 0,
+            blocks: 0,
+            memory: 0,
+            memory_objects: 0,
+            usage: None,
+            budget: None,
+        };
+        assert_eq!(report.fragmentation(), 0.0);
+
+        report.memory = 4096;
+        report.memory_objects = 1;
+        report.allocated = 1024;
+        report.blocks = 2;
+        assert_eq!(report.fragmentation(), 0.75);
+    }
+
+    #[test]
+    fn pad_request_skips_dedicated_requests() {
+        let request = gpu_alloc::Request {
+            size: 100,
+            align_mask: 15,
+            usage: gpu_alloc::UsageFlags::HOST_ACCESS,
+            memory_types: !0,
+        };
+        // VUID-VkMemoryDedicatedAllocateInfo-image-01433, the size has to match the image
+        assert_eq!(PADDING.pad_request(request, true), request);
+
+        let padded = PADDING.pad_request(request, false);
+        assert_eq!((padded.size, padded.align_mask), (1024, 1023));
+        assert_eq!(padded.memory_types, request.memory_types);
+    }
+
+    #[test]
+    fn pad_keeps_exact_sizes() {
+        let padding = Padding {
+            granularity: 1,
+            non_coherent_atom_size: 1,
+        };
+        assert_eq!(padding.pad(12, 4, true), (12, 3));
+        assert_eq!(padding.pad(0, 1, false), (0, 0));
+    }
+}
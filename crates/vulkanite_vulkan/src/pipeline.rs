@@ -6,6 +6,7 @@ use std::collections::BTreeMap;
 use std::ffi;
 use std::num::NonZeroU32;
 use std::sync::Arc;
+use tracing::warn;
 pub use vulkanite_types as vt;
 
 pub struct PipelineLayout {
@@ -19,6 +20,7 @@ pub struct BindGroupLayout {
 }
 
 pub struct PipelineLayoutInfo<'a> {
+    pub label: Option<&'a str>,
     pub flags: vt::PipelineLayoutFlags,
     pub bind_group_layouts: &'a [&'a BindGroupLayout],
     pub push_constant_ranges: &'a [vt::PushConstantRange],
@@ -40,6 +42,48 @@ pub enum BindingType {}
 pub struct ShaderStage<'a> {
     pub module: &'a ShaderModule,
     pub entry_point: &'a str,
+    /// specialization constants as `(constant id, value)`,
+    /// ids of named constants can be looked up with [`ShaderModule::constant_id`].
+    /// Must be empty for WGSL modules
+    pub constants: &'a [(u32, vt::SpecValue)],
+}
+
+impl ShaderStage<'_> {
+    fn validate(&self) -> Result<(), DeviceError> {
+        if self.module.wgsl && !self.constants.is_empty() {
+            return Err(DeviceError::WgslSpecialization);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct Specialization {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl Specialization {
+    pub(crate) fn new(constants: &[(u32, vt::SpecValue)]) -> Self {
+        let mut entries = Vec::with_capacity(constants.len());
+        let mut data = Vec::new();
+        for (id, value) in constants {
+            let bytes = value.to_ne_bytes();
+            entries.push(vk::SpecializationMapEntry {
+                constant_id: *id,
+                offset: data.len() as u32,
+                size: bytes.len(),
+            });
+            data.extend_from_slice(&bytes);
+        }
+        Self { entries, data }
+    }
+
+    pub(crate) fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
 }
 
 pub struct FragmentState<'a> {
@@ -47,6 +91,8 @@ pub struct FragmentState<'a> {
 }
 
 pub struct RasterPipelineInfo<'a> {
+    pub label: Option<&'a str>,
+    pub flags: vt::PipelineFlags,
     pub layout: &'a PipelineLayout,
     pub vertex: ShaderStage<'a>,
     pub vertex_buffers: &'a [vt::VertexBufferLayout<'a>],
@@ -55,9 +101,15 @@ pub struct RasterPipelineInfo<'a> {
     pub depth_stencil: Option<vt::DepthStencilState>,
     pub multisample: vt::MultisampleState,
     pub targets: &'a [vt::ColorTargetState],
+    pub cache: Option<&'a PipelineCache>,
 }
 
-pub struct ComputePipelineInfo {}
+pub struct ComputePipelineInfo<'a> {
+    pub label: Option<&'a str>,
+    pub layout: &'a PipelineLayout,
+    pub stage: ShaderStage<'a>,
+    pub cache: Option<&'a PipelineCache>,
+}
 
 pub struct RasterPipeline {
     pub(crate) device: Arc<DeviceShared>,
@@ -69,7 +121,91 @@ pub struct ComputePipeline {
     pub(crate) handle: vk::Pipeline,
 }
 
+pub struct PipelineCache {
+    pub(crate) device: Arc<DeviceShared>,
+    pub(crate) handle: vk::PipelineCache,
+}
+
+impl PipelineCache {
+    /// Serializes the cache, the result can be passed to [`Device::create_pipeline_cache`]
+    /// on the next launch.
+    pub fn data(&self) -> Result<Vec<u8>, DeviceError> {
+        unsafe {
+            self.device
+                .handle
+                .get_pipeline_cache_data(self.handle)
+                .map_err(DeviceError::Other)
+        }
+    }
+}
+
+/// Checks the `VkPipelineCacheHeaderVersionOne` at the start of `data`
+/// against the device the cache is going to be loaded into.
+fn validate_cache_header(
+    data: &[u8],
+    properties: &vk::PhysicalDeviceProperties,
+) -> Result<(), &'static str> {
+    const HEADER_SIZE: usize = 32;
+
+    // the header is little endian regardless of the host
+    let read_u32 = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    if data.len() < HEADER_SIZE {
+        return Err("data is smaller than the cache header");
+    }
+    if (read_u32(0) as usize) < HEADER_SIZE {
+        return Err("invalid header size");
+    }
+    if read_u32(4) != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err("unknown header version");
+    }
+    if read_u32(8) != properties.vendor_id {
+        return Err("vendor id doesn't match the adapter");
+    }
+    if read_u32(12) != properties.device_id {
+        return Err("device id doesn't match the adapter");
+    }
+    if data[16..32] != properties.pipeline_cache_uuid {
+        return Err("cache uuid doesn't match the adapter");
+    }
+
+    Ok(())
+}
+
 impl Device {
+    /// Creates a pipeline cache, optionally seeded with data from [`PipelineCache::data`].
+    /// Data that was written by a different adapter or driver is discarded.
+    pub fn create_pipeline_cache(
+        &self,
+        initial_data: Option<&[u8]>,
+    ) -> Result<PipelineCache, DeviceError> {
+        self.shared.check_lost()?;
+        let initial_data = match initial_data {
+            Some(data) => match validate_cache_header(data, &self.shared.adapter.properties) {
+                Ok(()) => data,
+                Err(reason) => {
+                    warn!("Pipeline cache discarded: {}", reason);
+                    &[]
+                }
+            },
+            None => &[],
+        };
+
+        let vk_info = vk::PipelineCacheCreateInfo::builder().initial_data(initial_data);
+
+        let handle = unsafe {
+            self.shared
+                .handle
+                .create_pipeline_cache(&vk_info, None)
+                .map_err(DeviceError::Other)?
+        };
+
+        Ok(PipelineCache {
+            device: self.shared.clone(),
+            handle,
+        })
+    }
+
     // pub fn create_bindgroup_layout(&self) -> Result<BindGroupLayout, DeviceError> {
     //
     // }
@@ -78,6 +214,7 @@ impl Device {
         &self,
         info: &PipelineLayoutInfo,
     ) -> Result<PipelineLayout, DeviceError> {
+        self.shared.check_lost()?;
         let vk_set_layouts = info
             .bind_group_layouts
             .iter()
@@ -106,6 +243,10 @@ impl Device {
                 .map_err(DeviceError::Other)?
         };
 
+        if let Some(label) = info.label {
+            self.shared.set_object_name(handle, label);
+        }
+
         let binding_arrays = BTreeMap::new();
         // for (group, &layout) in info.bind_group_layouts.iter().enumerate() {
         //
@@ -121,18 +262,47 @@ impl Device {
         &self,
         info: &RasterPipelineInfo<'_>,
     ) -> Result<RasterPipeline, DeviceError> {
-        let dynamic_states = [
+        self.shared.check_lost()?;
+        let mut dynamic_states = vec![
             vk::DynamicState::VIEWPORT,
             vk::DynamicState::SCISSOR,
             vk::DynamicState::BLEND_CONSTANTS,
             vk::DynamicState::STENCIL_REFERENCE,
         ];
 
+        if info.flags.contains(vt::PipelineFlags::DYNAMIC_CULL_MODE) {
+            dynamic_states.push(vk::DynamicState::CULL_MODE);
+        }
+        if info.flags.contains(vt::PipelineFlags::DYNAMIC_FRONT_FACE) {
+            dynamic_states.push(vk::DynamicState::FRONT_FACE);
+        }
+        if info.flags.contains(vt::PipelineFlags::DYNAMIC_TOPOLOGY) {
+            dynamic_states.push(vk::DynamicState::PRIMITIVE_TOPOLOGY);
+        }
+        if info.flags.contains(vt::PipelineFlags::DYNAMIC_DEPTH) {
+            dynamic_states.extend([
+                vk::DynamicState::DEPTH_TEST_ENABLE,
+                vk::DynamicState::DEPTH_WRITE_ENABLE,
+                vk::DynamicState::DEPTH_COMPARE_OP,
+            ]);
+        }
+
         let vertex_name = ffi::CString::new(info.vertex.entry_point).unwrap();
         // rust reference dies and rust compiler doesn't catch it
         #[allow(unused_assignments)] // idk why rust forces me to do this lmao
         let mut fragment_name = ffi::CString::new("").unwrap();
 
+        info.vertex.validate()?;
+        if let Some(fragment) = &info.fragment {
+            fragment.validate()?;
+        }
+
+        let vertex_specialization = Specialization::new(info.vertex.constants);
+        let vk_vertex_specialization = vertex_specialization.info();
+        let fragment_specialization =
+            Specialization::new(info.fragment.as_ref().map_or(&[], |f| f.constants));
+        let vk_fragment_specialization = fragment_specialization.info();
+
         let mut stages = Vec::new();
         let mut vertex_buffers = Vec::with_capacity(info.vertex_buffers.len());
         let mut vertex_attributes = Vec::new();
@@ -166,6 +336,7 @@ impl Device {
                 .name(&vertex_name)
                 .stage(vk::ShaderStageFlags::VERTEX)
                 .module(info.vertex.module.handle)
+                .specialization_info(&vk_vertex_specialization)
                 .build(),
         );
 
@@ -176,6 +347,7 @@ impl Device {
                     .name(&fragment_name)
                     .stage(vk::ShaderStageFlags::FRAGMENT)
                     .module(fragment.module.handle)
+                    .specialization_info(&vk_fragment_specialization)
                     .build(),
             );
         }
@@ -315,19 +487,161 @@ impl Device {
             .push_next(&mut pipeline_rendering_info);
 
         let vk_infos = [pipeline_info.build()];
+        let vk_cache = info
+            .cache
+            .map_or(vk::PipelineCache::null(), |cache| cache.handle);
 
         let mut pipeline_handles = unsafe {
             self.shared
                 .handle
-                .create_graphics_pipelines(vk::PipelineCache::null(), &vk_infos, None)
+                .create_graphics_pipelines(vk_cache, &vk_infos, None)
                 .map_err(|(_p, e)| DeviceError::Other(e))?
         };
 
         let handle = pipeline_handles.pop().unwrap();
+        if let Some(label) = info.label {
+            self.shared.set_object_name(handle, label);
+        }
 
         Ok(RasterPipeline {
             device: self.shared.clone(),
             handle,
         })
     }
+    pub fn create_compute_pipeline(
+        &self,
+        info: &ComputePipelineInfo<'_>,
+    ) -> Result<ComputePipeline, DeviceError> {
+        self.shared.check_lost()?;
+        info.stage.validate()?;
+
+        let name = ffi::CString::new(info.stage.entry_point).unwrap();
+        let specialization = Specialization::new(info.stage.constants);
+        let vk_specialization = specialization.info();
+
+        let vk_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&name)
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(info.stage.module.handle)
+            .specialization_info(&vk_specialization);
+
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .layout(info.layout.handle)
+            .stage(vk_stage.build());
+
+        let vk_infos = [pipeline_info.build()];
+        let vk_cache = info
+            .cache
+            .map_or(vk::PipelineCache::null(), |cache| cache.handle);
+
+        let mut pipeline_handles = unsafe {
+            self.shared
+                .handle
+                .create_compute_pipelines(vk_cache, &vk_infos, None)
+                .map_err(|(_p, e)| DeviceError::Other(e))?
+        };
+
+        let handle = pipeline_handles.pop().unwrap();
+        if let Some(label) = info.label {
+            self.shared.set_object_name(handle, label);
+        }
+
+        Ok(ComputePipeline {
+            device: self.shared.clone(),
+            handle,
+        })
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe { self.device.handle.destroy_pipeline_cache(self.handle, None) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2484,
+            pipeline_cache_uuid: [7; vk::UUID_SIZE],
+            ..Default::default()
+        }
+    }
+
+    /// Header written by the device of `properties`, followed by some cache data.
+    fn cache_data(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&32u32.to_le_bytes());
+        data.extend_from_slice(
+            &(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes(),
+        );
+        data.extend_from_slice(&properties.vendor_id.to_le_bytes());
+        data.extend_from_slice(&properties.device_id.to_le_bytes());
+        data.extend_from_slice(&properties.pipeline_cache_uuid);
+        data.extend_from_slice(&[0xab; 16]);
+        data
+    }
+
+    #[test]
+    fn matching_header_is_accepted() {
+        let properties = properties();
+        let data = cache_data(&properties);
+        assert_eq!(validate_cache_header(&data, &properties), Ok(()));
+        assert_eq!(validate_cache_header(&data[..32], &properties), Ok(()));
+    }
+
+    #[test]
+    fn short_data_is_rejected() {
+        let properties = properties();
+        let data = cache_data(&properties);
+        assert!(validate_cache_header(&data[..31], &properties).is_err());
+        assert!(validate_cache_header(&[], &properties).is_err());
+    }
+
+    #[test]
+    fn other_devices_are_rejected() {
+        let properties = properties();
+        let data = cache_data(&properties);
+
+        let other_vendor = vk::PhysicalDeviceProperties {
+            vendor_id: 0x1002,
+            ..properties
+        };
+        assert!(validate_cache_header(&data, &other_vendor).is_err());
+
+        let other_device = vk::PhysicalDeviceProperties {
+            device_id: 0x2485,
+            ..properties
+        };
+        assert!(validate_cache_header(&data, &other_device).is_err());
+
+        let other_driver = vk::PhysicalDeviceProperties {
+            pipeline_cache_uuid: [8; vk::UUID_SIZE],
+            ..properties
+        };
+        assert!(validate_cache_header(&data, &other_driver).is_err());
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let properties = properties();
+
+        let mut data = cache_data(&properties);
+        data[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            validate_cache_header(&data, &properties),
+            Err("unknown header version")
+        );
+
+        let mut data = cache_data(&properties);
+        data[0..4].copy_from_slice(&16u32.to_le_bytes());
+        assert_eq!(
+            validate_cache_header(&data, &properties),
+            Err("invalid header size")
+        );
+    }
 }
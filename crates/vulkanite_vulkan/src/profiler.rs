@@ -0,0 +1,468 @@
+use crate::command::CommandEncoder;
+use crate::deferred::LastUse;
+use crate::device::{Device, DeviceError};
+use crate::pipeline::vt;
+use crate::query::QuerySet;
+use ash::vk;
+use parking_lot::Mutex;
+use std::fmt::Write as _;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use std::{fmt, fs, io, thread};
+use tracing::{info, span, warn, Level};
+
+pub struct ProfilerInfo {
+    /// Number of query sets cycled through by `GpuProfiler::end_frame`, has to be larger than
+    /// the number of frames the GPU can lag behind, e.g. the encoders' `frames_in_flight` + 1.
+    pub frames_in_flight: u32,
+    pub max_scopes_per_frame: u32,
+    /// Keep all timings around for `GpuProfiler::chrome_trace`.
+    pub chrome_trace: bool,
+}
+
+impl Default for ProfilerInfo {
+    fn default() -> Self {
+        Self {
+            frames_in_flight: 3,
+            max_scopes_per_frame: 256,
+            chrome_trace: false,
+        }
+    }
+}
+
+/// GPU time of a scope, relative to the first scope of its frame.
+#[derive(Debug, Clone)]
+pub struct GpuTiming {
+    pub name: String,
+    pub start_ns: f64,
+    pub duration_ns: f64,
+}
+
+/// Collects GPU timings of `CommandEncoder::scope`s with timestamp queries.
+/// Results are read back when the profiler comes back to a frame in `end_frame`, once the GPU
+/// finished all of its scopes.
+#[derive(Clone)]
+pub struct GpuProfiler {
+    pub(crate) shared: Arc<Mutex<ProfilerShared>>,
+}
+
+impl fmt::Debug for GpuProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GpuProfiler").finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct ProfilerShared {
+    frames: Vec<ProfilerFrame>,
+    frame: usize,
+    frame_index: u64,
+    max_scopes: u32,
+    period: f64,
+    epoch: Instant,
+    trace: Option<Vec<TraceEvent>>,
+}
+
+struct ProfilerFrame {
+    set: QuerySet,
+    scopes: Vec<ScopeRecord>,
+    /// When the first scope of the frame was recorded, the GPU timeline is anchored to it.
+    recorded: Option<Instant>,
+    /// Times the profiler came back to the frame before the GPU finished its scopes.
+    laps: u32,
+}
+
+/// Laps after which the unfinished scopes of a frame are discarded, they most likely belong to
+/// an encoder that was dropped or never submitted.
+const STALE_LAPS: u32 = 4;
+
+struct ScopeRecord {
+    name: String,
+    query: u32,
+}
+
+struct TraceEvent {
+    name: String,
+    thread: String,
+    start_us: f64,
+    duration_us: f64,
+}
+
+impl GpuProfiler {
+    /// Reads back the results of the frame the profiler moves on to, emits them as `tracing`
+    /// events with the target `vulkanite::gpu` and returns them. Call once per frame.
+    pub fn end_frame(&self) -> Vec<GpuTiming> {
+        let mut shared = self.shared.lock();
+        shared.frame = (shared.frame + 1) % shared.frames.len();
+        shared.frame_index += 1;
+
+        let timings = shared.read_frame();
+        let frame_index = shared.frame_index;
+        for timing in &timings {
+            info!(
+                target: "vulkanite::gpu",
+                frame = frame_index,
+                scope = timing.name.as_str(),
+                duration_ns = timing.duration_ns,
+                "gpu scope"
+            );
+        }
+
+        timings
+    }
+
+    /// CPU scope that enters a `tracing` span and, with `ProfilerInfo::chrome_trace`, ends up on
+    /// the same timeline as the GPU scopes.
+    pub fn cpu_scope(&self, name: &str) -> CpuScope {
+        CpuScope {
+            profiler: self.clone(),
+            name: name.to_string(),
+            start: Instant::now(),
+            _span: span!(Level::INFO, "cpu scope", name).entered(),
+        }
+    }
+
+    /// All timings recorded so far in the Chrome trace event format,
+    /// GPU scopes are placed on the CPU timeline at the time their frame was recorded.
+    pub fn chrome_trace(&self) -> String {
+        chrome_trace_json(self.shared.lock().trace.iter().flatten())
+    }
+
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.chrome_trace())
+    }
+}
+
+impl ProfilerShared {
+    /// Allocates the begin and end query of a new scope in the current frame.
+    fn begin_scope(&mut self, name: &str) -> Option<(vk::QueryPool, u32, Arc<LastUse>)> {
+        let max_scopes = self.max_scopes;
+        let frame = &mut self.frames[self.frame];
+        if frame.scopes.len() as u32 >= max_scopes {
+            warn!("Profiler ran out of queries, dropped scope {}", name);
+            return None;
+        }
+
+        frame.recorded.get_or_insert_with(Instant::now);
+        let query = frame.scopes.len() as u32 * 2;
+        frame.scopes.push(ScopeRecord {
+            name: name.to_string(),
+            query,
+        });
+
+        Some((frame.set.handle, query, frame.set.last_use.clone()))
+    }
+
+    fn read_frame(&mut self) -> Vec<GpuTiming> {
+        let period = self.period;
+        let epoch = self.epoch;
+        let frame = &mut self.frames[self.frame];
+        if frame.scopes.is_empty() {
+            return Vec::new();
+        }
+
+        let count = frame.scopes.len() as u32 * 2;
+        // value and availability of every query
+        let mut results = vec![[0u64; 2]; count as usize];
+        let result = unsafe {
+            frame.set.device.handle.get_query_pool_results(
+                frame.set.handle,
+                0,
+                count,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        match result {
+            Ok(()) | Err(vk::Result::NOT_READY) => {}
+            Err(err) => {
+                warn!("Failed to read profiler queries: {}", err);
+                return Vec::new();
+            }
+        }
+
+        // the queries can only be reset once the GPU wrote all of them, the frame keeps its
+        // scopes and new scopes are added after them until then
+        if results.iter().any(|[_, available]| *available == 0) {
+            frame.laps += 1;
+            if frame.laps < STALE_LAPS {
+                warn!(
+                    "Profiler frame isn't finished yet, it's read again when the profiler comes \
+                     back to it, increase frames_in_flight"
+                );
+                return Vec::new();
+            }
+
+            warn!(
+                "Discarded {} profiler scopes that weren't finished after {} laps, submit the \
+                 encoders they were recorded with",
+                frame.scopes.len(),
+                STALE_LAPS
+            );
+            frame.scopes.clear();
+            frame.recorded = None;
+            frame.laps = 0;
+            let _ = frame.set.reset(0..frame.set.count);
+            return Vec::new();
+        }
+
+        let mut timings = Vec::with_capacity(frame.scopes.len());
+        let origin = results
+            .iter()
+            .filter(|[_, available]| *available != 0)
+            .map(|[value, _]| *value)
+            .min()
+            .unwrap_or(0);
+
+        for scope in frame.scopes.drain(..) {
+            let [begin, _] = results[scope.query as usize];
+            let [end, _] = results[scope.query as usize + 1];
+            timings.push(GpuTiming {
+                name: scope.name,
+                start_ns: (begin - origin) as f64 * period,
+                duration_ns: end.saturating_sub(begin) as f64 * period,
+            });
+        }
+
+        if let (Some(trace), Some(recorded)) = (self.trace.as_mut(), frame.recorded) {
+            let anchor_us = recorded.duration_since(epoch).as_secs_f64() * 1_000_000.0;
+            trace.extend(timings.iter().map(|timing| TraceEvent {
+                name: timing.name.clone(),
+                thread: "gpu".to_string(),
+                start_us: anchor_us + timing.start_ns / 1000.0,
+                duration_us: timing.duration_ns / 1000.0,
+            }));
+        }
+
+        frame.recorded = None;
+        frame.laps = 0;
+        // support is checked when the profiler is created
+        let _ = frame.set.reset(0..frame.set.count);
+
+        timings
+    }
+}
+
+/// Ends the timestamp query and debug label of a `CommandEncoder::scope` when dropped,
+/// derefs to the encoder so commands can be recorded inside the scope.
+pub struct ProfileScope<'a> {
+    pub(crate) encoder: &'a mut CommandEncoder,
+    pub(crate) query: Option<(vk::QueryPool, u32)>,
+}
+
+impl<'a> Deref for ProfileScope<'a> {
+    type Target = CommandEncoder;
+
+    fn deref(&self) -> &Self::Target {
+        self.encoder
+    }
+}
+
+impl<'a> DerefMut for ProfileScope<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.encoder
+    }
+}
+
+impl<'a> Drop for ProfileScope<'a> {
+    fn drop(&mut self) {
+        let mut handle = self.encoder.handle.lock();
+        if handle.active == vk::CommandBuffer::null() {
+            return;
+        }
+        unsafe {
+            if let Some((pool, query)) = self.query {
+                handle.write_timestamp(
+                    pool,
+                    query + 1,
+                    crate::conv::map_stage_flags(vt::StageFlags::BOTTOM_OF_PIPE),
+                );
+            }
+            handle.end_label();
+        }
+    }
+}
+
+impl CommandEncoder {
+    /// Profiles the commands recorded until the returned guard is dropped, when a profiler is
+    /// set with `set_profiler`, and wraps them in a debug label.
+    pub fn scope(&mut self, name: &str) -> ProfileScope<'_> {
+        let query = {
+            let mut handle = self.handle.lock();
+            if handle.active == vk::CommandBuffer::null() {
+                panic!("no active encoding");
+            }
+
+            let query = match &handle.profiler {
+                Some(profiler) => profiler.shared.lock().begin_scope(name),
+                None => None,
+            };
+            let query = query.map(|(pool, query, last_use)| {
+                handle.used.add(&last_use);
+                (pool, query)
+            });
+
+            unsafe {
+                handle.begin_label(name, [0.0; 4]);
+                if let Some((pool, query)) = query {
+                    handle.write_timestamp(
+                        pool,
+                        query,
+                        crate::conv::map_stage_flags(vt::StageFlags::TOP_OF_PIPE),
+                    );
+                }
+            }
+
+            query
+        };
+
+        ProfileScope {
+            encoder: self,
+            query,
+        }
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<&GpuProfiler>) {
+        self.handle.lock().profiler = profiler.cloned();
+    }
+}
+
+pub struct CpuScope {
+    profiler: GpuProfiler,
+    name: String,
+    start: Instant,
+    _span: span::EnteredSpan,
+}
+
+impl Drop for CpuScope {
+    fn drop(&mut self) {
+        let mut shared = self.profiler.shared.lock();
+        let epoch = shared.epoch;
+        if let Some(trace) = shared.trace.as_mut() {
+            let thread = thread::current();
+            trace.push(TraceEvent {
+                name: std::mem::take(&mut self.name),
+                thread: thread
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("{:?}", thread.id())),
+                start_us: self.start.duration_since(epoch).as_secs_f64() * 1_000_000.0,
+                duration_us: self.start.elapsed().as_secs_f64() * 1_000_000.0,
+            });
+        }
+    }
+}
+
+/// `events` in the Chrome trace event format.
+fn chrome_trace_json<'a>(events: impl IntoIterator<Item = &'a TraceEvent>) -> String {
+    let mut json = String::from("{\"traceEvents\":[");
+    for (i, event) in events.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":\"{}\",\"ts\":{:.3},\"dur\":{:.3}}}",
+            escape_json(&event.name),
+            escape_json(&event.thread),
+            event.start_us,
+            event.duration_us
+        );
+    }
+    json.push_str("]}");
+    json
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Device {
+    /// Needs `Adapter::supports_host_query_reset`, the query sets are reset from the host when
+    /// their results were read.
+    pub fn create_profiler(&self, info: &ProfilerInfo) -> Result<GpuProfiler, DeviceError> {
+        if !self.shared.adapter.host_query_reset {
+            return Err(DeviceError::MissingFeature("host_query_reset"));
+        }
+
+        let frames = (0..info.frames_in_flight.max(1))
+            .map(|_| {
+                Ok(ProfilerFrame {
+                    set: self.create_query_set(
+                        vt::QueryType::Timestamp,
+                        info.max_scopes_per_frame * 2,
+                    )?,
+                    scopes: Vec::new(),
+                    recorded: None,
+                    laps: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, DeviceError>>()?;
+
+        Ok(GpuProfiler {
+            shared: Arc::new(Mutex::new(ProfilerShared {
+                frames,
+                frame: 0,
+                frame_index: 0,
+                max_scopes: info.max_scopes_per_frame,
+                period: self.shared.adapter.timestamp_period() as f64,
+                epoch: Instant::now(),
+                trace: info.chrome_trace.then(Vec::new),
+            })),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(escape_json("shadow pass"), "shadow pass");
+        assert_eq!(escape_json("a \"b\" \\ c"), "a \\\"b\\\" \\\\ c");
+        assert_eq!(escape_json("line\nbreak"), "line\\nbreak");
+        assert_eq!(escape_json("tab\t\u{1}"), "tab\\u0009\\u0001");
+        assert_eq!(escape_json("höhe"), "höhe");
+    }
+
+    #[test]
+    fn chrome_trace_lists_complete_events() {
+        assert_eq!(chrome_trace_json(&[]), "{\"traceEvents\":[]}");
+
+        let events = [
+            TraceEvent {
+                name: "frame".to_string(),
+                thread: "main".to_string(),
+                start_us: 10.0,
+                duration_us: 2.5,
+            },
+            TraceEvent {
+                name: "\"quoted\"".to_string(),
+                thread: "gpu".to_string(),
+                start_us: 11.25,
+                duration_us: 0.25,
+            },
+        ];
+        assert_eq!(
+            chrome_trace_json(&events),
+            "{\"traceEvents\":[\
+             {\"name\":\"frame\",\"ph\":\"X\",\"pid\":0,\"tid\":\"main\",\"ts\":10.000,\"dur\":2.500},\
+             {\"name\":\"\\\"quoted\\\"\",\"ph\":\"X\",\"pid\":0,\"tid\":\"gpu\",\"ts\":11.250,\"dur\":0.250}\
+             ]}"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::deferred::{Deferred, LastUse};
+use crate::device::{Device, DeviceError, DeviceShared};
+use crate::pipeline::vt;
+use ash::vk;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Destroyed once the submissions using it have finished.
+#[derive(Debug)]
+pub struct QuerySet {
+    pub(crate) device: Arc<DeviceShared>,
+    pub(crate) handle: vk::QueryPool,
+    pub(crate) ty: vt::QueryType,
+    pub(crate) count: u32,
+    pub(crate) last_use: Arc<LastUse>,
+}
+
+impl QuerySet {
+    pub fn ty(&self) -> vt::QueryType {
+        self.ty
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Resets `range` from the host, the queries must not be in use by the GPU.
+    /// Queries have to be reset before they can be written again.
+    /// Needs `Adapter::supports_host_query_reset`, use `CommandEncoder::reset_query_set` otherwise.
+    pub fn reset(&self, range: Range<u32>) -> Result<(), DeviceError> {
+        check_queries(&range, self.count);
+        if !self.device.adapter.host_query_reset {
+            return Err(DeviceError::MissingFeature("host_query_reset"));
+        }
+        self.device.check_lost()?;
+        unsafe {
+            self.device
+                .handle
+                .reset_query_pool(self.handle, range.start, range.len() as u32);
+        }
+        Ok(())
+    }
+
+    /// Blocks until all queries in `range` are available and returns their raw values,
+    /// `ty().values_per_query()` per query.
+    pub fn read(&self, range: Range<u32>) -> Result<Vec<u64>, DeviceError> {
+        check_queries(&range, self.count);
+        self.device.check_lost()?;
+        let values_per_query = self.ty.values_per_query() as usize;
+        let mut values = vec![0u64; range.len() * values_per_query];
+        // pipeline statistics queries write one value per counter, ash's wrapper assumes one
+        let stride = (mem::size_of::<u64>() * values_per_query) as vk::DeviceSize;
+        unsafe {
+            (self.device.handle.fp_v1_0().get_query_pool_results)(
+                self.device.handle.handle(),
+                self.handle,
+                range.start,
+                range.len() as u32,
+                mem::size_of_val(values.as_slice()),
+                values.as_mut_ptr().cast(),
+                stride,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+            .result()
+            .map_err(|err| self.device.map_err(err, None))?;
+        }
+        Ok(values)
+    }
+
+    /// Same as `read` for a timestamp query set, converted from ticks to nanoseconds.
+    pub fn read_timestamps(&self, range: Range<u32>) -> Result<Vec<f64>, DeviceError> {
+        let period = self.device.adapter.timestamp_period() as f64;
+        Ok(self
+            .read(range)?
+            .into_iter()
+            .map(|ticks| ticks as f64 * period)
+            .collect())
+    }
+}
+
+/// Panics unless `range` is an ordered range of queries inside a set of `count`.
+pub(crate) fn check_queries(range: &Range<u32>, count: u32) {
+    assert!(
+        range.start <= range.end && range.end <= count,
+        "queries {:?} out of bounds of a query set of {}",
+        range,
+        count
+    );
+}
+
+/// Panics unless `index` is a query inside a set of `count`.
+pub(crate) fn check_query(index: u32, count: u32) {
+    assert!(
+        index < count,
+        "query {} out of bounds of a query set of {}",
+        index,
+        count
+    );
+}
+
+impl Device {
+    /// The queries start out reset if the adapter supports host query resets, otherwise they have
+    /// to be reset with `CommandEncoder::reset_query_set` before they are first written.
+    pub fn create_query_set(&self, ty: vt::QueryType, count: u32) -> Result<QuerySet, DeviceError> {
+        self.shared.check_lost()?;
+        let mut info = vk::QueryPoolCreateInfo::builder().query_count(count);
+        info = match ty {
+            vt::QueryType::Timestamp => info.query_type(vk::QueryType::TIMESTAMP),
+            vt::QueryType::Occlusion => info.query_type(vk::QueryType::OCCLUSION),
+            vt::QueryType::PipelineStatistics(types) => info
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .pipeline_statistics(vk::QueryPipelineStatisticFlags::from_raw(types.bits())),
+        };
+
+        let handle = unsafe {
+            self.shared
+                .handle
+                .create_query_pool(&info, None)
+                .map_err(DeviceError::Other)?
+        };
+
+        let set = QuerySet {
+            device: self.shared.clone(),
+            handle,
+            ty,
+            count,
+            last_use: Arc::default(),
+        };
+        if self.shared.adapter.host_query_reset {
+            set.reset(0..count)?;
+        }
+
+        Ok(set)
+    }
+}
+
+impl Drop for QuerySet {
+    fn drop(&mut self) {
+        self.device
+            .defer_used(self.last_use.clone(), Deferred::QueryPool(self.handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_ranges_inside_the_set() {
+        check_queries(&(0..4), 4);
+        check_queries(&(4..4), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn query_range_past_the_end_panics() {
+        check_queries(&(2..5), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn query_past_the_end_panics() {
+        check_query(4, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn inverted_query_range_panics() {
+        #[allow(clippy::reversed_empty_ranges)]
+        check_queries(&(3..1), 4);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::command::CommandBuffer;
+use crate::command::{CommandBuffer, VkCommandEncoder};
+use crate::deferred::UsedResources;
 use crate::device::{DeviceError, DeviceShared};
 use crate::sync::{BinarySemaphore, Fence};
 use ash::vk;
@@ -9,6 +10,8 @@ use tracing::info;
 #[derive(Debug)]
 pub struct Queue {
     pub(crate) handle: Mutex<vk::Queue>,
+    /// Index of the queue's timeline in the device's destroy queue.
+    pub(crate) timeline: usize,
     pub(crate) device: Arc<DeviceShared>,
     pub(crate) id: u64,
     pub(crate) family: u32,
@@ -28,20 +31,81 @@ impl Queue {
         // wait_timeline_semaphores: &[(&TimelineSemaphore, u64)],
         fence: Option<&Fence>,
     ) -> Result<(), DeviceError> {
+        self.device.check_lost()?;
+
         let vk_fence = match fence {
             Some(fence) => fence.handle,
             None => vk::Fence::null(),
         };
 
-        let submit_command_buffers = command_buffers
-            .into_iter()
-            .map(|buffer| buffer.handle)
-            .collect::<Vec<vk::CommandBuffer>>();
+        // encoders in this submission and the frames their buffers came from
+        let mut encoders: Vec<(Arc<Mutex<VkCommandEncoder>>, Vec<usize>)> = Vec::new();
+        let mut submit_command_buffers = Vec::new();
+        let mut used = UsedResources::default();
+
+        for buffer in command_buffers {
+            let CommandBuffer {
+                handles,
+                encoder,
+                frame,
+                tracker,
+                bundles,
+                used: buffer_used,
+            } = buffer;
+            used.extend(&buffer_used);
+            let mut frames = vec![frame];
+
+            if let Some(tracker) = tracker {
+                let (image_barriers, buffer_barriers) = tracker.resolve();
+                if !image_barriers.is_empty() || !buffer_barriers.is_empty() {
+                    let mut encoder = encoder.lock();
+                    let fixup =
+                        unsafe { encoder.record_barriers(&image_barriers, &buffer_barriers)? };
+                    frames.push(encoder.frame);
+                    submit_command_buffers.push(fixup);
+                }
+            }
+            submit_command_buffers.extend(handles);
+
+            for (encoder, frames) in std::iter::once((encoder, frames)).chain(
+                bundles
+                    .into_iter()
+                    .map(|(encoder, frame)| (encoder, vec![frame])),
+            ) {
+                match encoders.iter_mut().find(|(e, _)| Arc::ptr_eq(e, &encoder)) {
+                    Some((_, used)) => used.extend(frames),
+                    None => encoders.push((encoder, frames)),
+                }
+            }
+        }
+
+        // the timeline values are reserved and advanced under the same locks, held across the
+        // submit, so concurrent submissions never signal the same value. Encoders are locked in
+        // address order to not deadlock with another submission sharing them.
+        encoders.sort_by_key(|(encoder, _)| Arc::as_ptr(encoder));
+        let mut locked = encoders
+            .iter()
+            .map(|(encoder, _)| encoder.lock())
+            .collect::<Vec<_>>();
+        let mut destroy = self.device.destroy.lock();
 
-        let submit_signals = signal_binary_semaphores
+        let mut submit_signals = signal_binary_semaphores
             .iter()
             .map(|sema| sema.handle)
             .collect::<Vec<_>>();
+        let mut submit_signal_values = vec![0; submit_signals.len()];
+
+        for encoder in &locked {
+            submit_signals.push(encoder.timeline);
+            submit_signal_values.push(encoder.timeline_value + 1);
+        }
+
+        let queue_value = destroy.timelines[self.timeline].submitted + 1;
+        submit_signals.push(destroy.timelines[self.timeline].semaphore);
+        submit_signal_values.push(queue_value);
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(&submit_signal_values);
 
         let mut submit_wait_masks = Vec::new();
 
@@ -58,15 +122,43 @@ impl Queue {
             .wait_semaphores(&submit_waits)
             .wait_dst_stage_mask(&submit_wait_masks)
             .signal_semaphores(&submit_signals)
+            .push_next(&mut timeline_info)
             .build();
 
+        *self.device.last_submitted.lock() = locked
+            .iter()
+            .filter_map(|encoder| encoder.label.clone())
+            .collect();
+
         let handle = self.handle.lock();
-        unsafe {
+        let result = unsafe {
             self.device
                 .handle
                 .queue_submit(*handle, &[submit_info], vk_fence)
-                .unwrap();
+        };
+        let queue = *handle;
+        drop(handle);
+
+        // only advance the timelines once the signal is actually queued
+        if result.is_ok() {
+            destroy.timelines[self.timeline].submitted = queue_value;
+            used.submitted(self.timeline, queue_value);
+            for (encoder, (_, frames)) in locked.iter_mut().zip(&encoders) {
+                encoder.timeline_value += 1;
+                let value = encoder.timeline_value;
+                for &frame in frames {
+                    encoder.frames[frame].submitted = value;
+                }
+            }
         }
+        drop(destroy);
+        drop(locked);
+
+        // the device lost callback may drop resources, so it runs without the locks
+        result.map_err(|err| self.device.map_err(err, Some(queue)))?;
+
+        self.device.maintain();
+
         Ok(())
         // let mut submit_signals = Vec::new();
         // let mut submit_signals_values = Vec::new();
@@ -171,10 +263,7 @@ impl<'q> QueueFamily<'q> {
 
 impl Drop for Queue {
     fn drop(&mut self) {
-        let device = self.device.handle.clone();
-        unsafe {
-            device.device_wait_idle().unwrap();
-            info!("Destroyed: Queue");
-        }
+        // the queue timeline lives until the device is destroyed
+        info!("Destroyed: Queue");
     }
 }
@@ -3,15 +3,46 @@ use ash::vk;
 use naga::back::spv;
 use naga::back::spv::WriterFlags;
 use naga::front::wgsl;
-use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::valid::{ValidationFlags, Validator};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
 use thiserror::Error;
 
-#[derive(Debug, Default, Copy, Clone)]
+pub use naga::back::spv::{BindingInfo, BindingMap};
+pub use naga::proc::{BoundsCheckPolicies, BoundsCheckPolicy};
+pub use naga::valid::Capabilities as ShaderCapabilities;
+pub use naga::ResourceBinding;
+
+#[derive(Debug, Clone)]
 pub struct WgslShaderCompileInfo {
-    debug: bool,
-    flip_y: bool,
+    /// emit debug info (names, source locations) into the SPIR-V, off by default
+    pub debug: bool,
+    /// flip y of the output position to match Vulkan's coordinate space, off by default
+    pub flip_y: bool,
+    /// (major, minor) SPIR-V version to target
+    pub spirv_version: (u8, u8),
+    /// per-binding overrides, e.g. sizes of unsized binding arrays
+    pub binding_map: BindingMap,
+    /// remaps bind groups: `group in shader -> descriptor set in pipeline layout`
+    pub group_map: BTreeMap<u32, u32>,
+    /// capabilities the shader is validated against, `None` derives them from the adapter
+    pub capabilities: Option<ShaderCapabilities>,
+    pub bounds_checks: BoundsCheckPolicies,
+}
+
+impl Default for WgslShaderCompileInfo {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            flip_y: false,
+            spirv_version: (1, 0),
+            binding_map: BindingMap::new(),
+            group_map: BTreeMap::new(),
+            capabilities: None,
+            bounds_checks: BoundsCheckPolicies::default(),
+        }
+    }
 }
 
 pub enum ShaderSource<'a> {
@@ -31,6 +62,64 @@ pub enum ShaderSource<'a> {
 
 pub struct ShaderModule {
     pub(crate) handle: vk::ShaderModule,
+    pub(crate) spec_ids: HashMap<String, u32>,
+    /// Compiled from WGSL, which can't declare specialization constants yet.
+    pub(crate) wgsl: bool,
+}
+
+impl ShaderModule {
+    /// Looks up the specialization constant id of a named constant
+    /// (`layout(constant_id = N) const` in GLSL).
+    ///
+    /// Names are read from the SPIR-V debug info, so they are only available if the
+    /// compiler emitted `OpName`s. naga 0.10 can't parse WGSL `override` declarations yet,
+    /// so WGSL modules don't have any.
+    pub fn constant_id(&self, name: &str) -> Option<u32> {
+        self.spec_ids.get(name).copied()
+    }
+}
+
+/// collects `name -> SpecId` of all specialization constants in a SPIR-V binary
+fn reflect_spec_ids(words: &[u32]) -> HashMap<String, u32> {
+    const OP_NAME: u32 = 5;
+    const OP_DECORATE: u32 = 71;
+    const DECORATION_SPEC_ID: u32 = 1;
+
+    let mut names = HashMap::new();
+    let mut spec_ids = HashMap::new();
+
+    // skip the 5 word header
+    let mut cursor = 5;
+    while cursor < words.len() {
+        let word_count = (words[cursor] >> 16) as usize;
+        let opcode = words[cursor] & 0xffff;
+        if word_count == 0 || cursor + word_count > words.len() {
+            break;
+        }
+        let operands = &words[cursor + 1..cursor + word_count];
+
+        match opcode {
+            OP_NAME if operands.len() >= 2 => {
+                let bytes = operands[1..]
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .take_while(|&byte| byte != 0)
+                    .collect::<Vec<_>>();
+                names.insert(operands[0], String::from_utf8_lossy(&bytes).into_owned());
+            }
+            OP_DECORATE if operands.len() >= 3 && operands[1] == DECORATION_SPEC_ID => {
+                spec_ids.insert(operands[0], operands[2]);
+            }
+            _ => {}
+        }
+
+        cursor += word_count;
+    }
+
+    spec_ids
+        .into_iter()
+        .filter_map(|(target, id)| names.remove(&target).map(|name| (name, id)))
+        .collect()
 }
 
 pub enum ShaderKind {
@@ -77,33 +166,65 @@ pub enum ShaderError {
     #[error(transparent)]
     SpirVParse(#[from] spv::Error),
     #[error(transparent)]
-    Validation(#[from] naga::WithSpan<naga::valid::ValidationError>),
+    Validation(Box<ShaderValidationError>),
     #[error(transparent)]
     Device(#[from] DeviceError),
 }
 
+#[derive(Error, Debug)]
+#[error("{rendered}")]
+pub struct ShaderValidationError {
+    #[source]
+    pub error: naga::WithSpan<naga::valid::ValidationError>,
+    /// the error rendered against the WGSL source
+    pub rendered: String,
+}
+
 impl Device {
     pub fn create_shader_module(
         &self,
         source: ShaderSource<'_>,
     ) -> Result<ShaderModule, ShaderError> {
-        match source {
+        self.shared.check_lost()?;
+        Ok(match source {
             ShaderSource::Wgsl(source, info) => {
-                let module = wgsl::parse_str(&source).map_err(|e| ShaderError::WgslParse(e))?;
-                let mut opts = spv::Options::default();
+                let mut module = wgsl::parse_str(&source).map_err(|e| ShaderError::WgslParse(e))?;
 
-                if info.debug {
-                    opts.flags.insert(WriterFlags::DEBUG);
-                }
-                if info.flip_y {
-                    opts.flags.insert(WriterFlags::ADJUST_COORDINATE_SPACE);
+                if !info.group_map.is_empty() {
+                    for (_, var) in module.global_variables.iter_mut() {
+                        if let Some(binding) = &mut var.binding {
+                            if let Some(&group) = info.group_map.get(&binding.group) {
+                                binding.group = group;
+                            }
+                        }
+                    }
                 }
 
-                let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+                let mut opts = spv::Options {
+                    lang_version: info.spirv_version,
+                    binding_map: info.binding_map.clone(),
+                    bounds_check_policies: info.bounds_checks,
+                    ..spv::Options::default()
+                };
+
+                opts.flags.set(WriterFlags::DEBUG, info.debug);
+                opts.flags
+                    .set(WriterFlags::ADJUST_COORDINATE_SPACE, info.flip_y);
+
+                let capabilities = info
+                    .capabilities
+                    .unwrap_or_else(|| self.shared.adapter.shader_capabilities());
+
+                let module_info = Validator::new(ValidationFlags::all(), capabilities)
                     .validate(&module)
-                    .map_err(|e| ShaderError::Validation(e))?;
+                    .map_err(|error| {
+                        ShaderError::Validation(Box::new(ShaderValidationError {
+                            rendered: error.emit_to_string(&source),
+                            error,
+                        }))
+                    })?;
 
-                let spv = spv::write_vec(&module, &info, &opts, None)
+                let spv = spv::write_vec(&module, &module_info, &opts, None)
                     .map_err(|e| ShaderError::SpirVParse(e))?;
 
                 let vk_info = vk::ShaderModuleCreateInfo::builder()
@@ -118,7 +239,11 @@ impl Device {
                         .map_err(ShaderError::Device)?
                 };
 
-                Ok(ShaderModule { handle })
+                ShaderModule {
+                    handle,
+                    spec_ids: reflect_spec_ids(&spv),
+                    wgsl: true,
+                }
             }
 
             ShaderSource::Glsl {
@@ -144,7 +269,11 @@ impl Device {
                         .map_err(ShaderError::Device)?
                 };
 
-                Ok(ShaderModule { handle })
+                ShaderModule {
+                    handle,
+                    spec_ids: reflect_spec_ids(artifact.as_binary()),
+                    wgsl: false,
+                }
             }
             ShaderSource::Hlsl { .. } => {
                 unimplemented!()
@@ -165,8 +294,23 @@ impl Device {
                         .map_err(ShaderError::Device)?
                 };
 
-                Ok(ShaderModule { handle })
+                ShaderModule {
+                    handle,
+                    spec_ids: reflect_spec_ids(&spirv),
+                    wgsl: false,
+                }
             }
-        }
+        })
+    }
+
+    /// Same as `create_shader_module`, with a name shown by debuggers.
+    pub fn create_shader_module_with_label(
+        &self,
+        source: ShaderSource<'_>,
+        label: &str,
+    ) -> Result<ShaderModule, ShaderError> {
+        let module = self.create_shader_module(source)?;
+        self.shared.set_object_name(module.handle, label);
+        Ok(module)
     }
 }
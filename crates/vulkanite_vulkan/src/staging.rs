@@ -0,0 +1,337 @@
+use crate::buffer::{Buffer, BufferInitInfo};
+use crate::command::CommandEncoder;
+use crate::device::{Device, DeviceError, DeviceShared};
+use crate::memory::{align_up, AllocationError};
+use crate::sync::{Fence, TimelineSemaphore};
+use ash::vk;
+use gpu_alloc_ash::AshMemoryDevice;
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::Arc;
+use vulkanite_types as vt;
+
+/// Marks the end of the GPU work that reads the chunks of a frame, kept alive by the belt
+/// until it was reached.
+#[derive(Debug, Clone)]
+pub enum FrameSignal {
+    /// Signalled by the frame's submission. The belt reads its status in `recall`, so it has to
+    /// be recalled before the fence is reset.
+    Fence(Arc<Fence>),
+    /// Reaches the value once the frame's submission finished.
+    Timeline(Arc<TimelineSemaphore>, u64),
+}
+
+/// Host visible buffer that stays mapped while the belt owns it.
+struct Chunk {
+    buffer: Buffer,
+    ptr: NonNull<u8>,
+    space: ChunkSpace,
+}
+
+/// Part of a chunk that is handed out, copies start at `COPY_BUFFER_ALIGNMENT`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChunkSpace {
+    size: vt::BufferAddress,
+    /// Start of the unused part.
+    offset: vt::BufferAddress,
+}
+
+impl ChunkSpace {
+    fn new(size: vt::BufferAddress) -> Self {
+        Self { size, offset: 0 }
+    }
+
+    fn fits(&self, size: vt::BufferAddress) -> bool {
+        align_up(self.offset, vt::COPY_BUFFER_ALIGNMENT)
+            .checked_add(size)
+            .is_some_and(|end| end <= self.size)
+    }
+
+    /// Hands out `size` bytes, which have to fit, and returns their offset.
+    fn allocate(&mut self, size: vt::BufferAddress) -> vt::BufferAddress {
+        debug_assert!(self.fits(size));
+        let offset = align_up(self.offset, vt::COPY_BUFFER_ALIGNMENT);
+        self.offset = offset + size;
+        offset
+    }
+}
+
+/// Smallest of the `free` chunks that can take `size` bytes.
+fn find_free<'a>(
+    free: impl IntoIterator<Item = &'a ChunkSpace>,
+    size: vt::BufferAddress,
+) -> Option<usize> {
+    free.into_iter()
+        .enumerate()
+        .filter(|(_, space)| space.size >= size)
+        .min_by_key(|(_, space)| space.size)
+        .map(|(index, _)| index)
+}
+
+/// Size of a new chunk for a write of `size` bytes, larger writes get a chunk of their own.
+/// `None` if the aligned size overflows.
+fn new_chunk_size(
+    chunk_size: vt::BufferAddress,
+    size: vt::BufferAddress,
+) -> Option<vt::BufferAddress> {
+    size.checked_add(vt::COPY_BUFFER_ALIGNMENT - 1)?;
+    Some(chunk_size.max(align_up(size, vt::COPY_BUFFER_ALIGNMENT)))
+}
+
+/// Whether `size` bytes at `offset` fit into a buffer of `buffer_size` bytes.
+fn write_fits(
+    offset: vt::BufferAddress,
+    size: vt::BufferAddress,
+    buffer_size: vt::BufferAddress,
+) -> bool {
+    offset
+        .checked_add(size)
+        .is_some_and(|end| end <= buffer_size)
+}
+
+// the mapping is only written through `&mut StagingBelt`
+unsafe impl Send for Chunk {}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let device = self.buffer.device.clone();
+        if let Some(block) = self.buffer.block.get_mut().as_mut() {
+            unsafe { block.unmap(AshMemoryDevice::wrap(&device.handle)) };
+        }
+    }
+}
+
+/// Ring of host visible chunks for uploads that change every frame, e.g. uniforms or dynamic
+/// vertex data.
+///
+/// `write_buffer` hands out a part of a chunk and records its copy into the target buffer.
+/// `finish` closes the chunks written since the last call once the frame is recorded, and
+/// `recall` reuses the closed chunks whose `FrameSignal` was reached.
+pub struct StagingBelt {
+    device: Arc<DeviceShared>,
+    chunk_size: vt::BufferAddress,
+    active: Vec<Chunk>,
+    closed: Vec<(FrameSignal, Vec<Chunk>)>,
+    free: Vec<Chunk>,
+}
+
+impl Device {
+    /// Chunks are `chunk_size` bytes, larger writes get a chunk of their own.
+    pub fn create_staging_belt(&self, chunk_size: vt::BufferAddress) -> StagingBelt {
+        StagingBelt {
+            device: self.shared.clone(),
+            chunk_size: align_up(chunk_size.max(1), vt::COPY_BUFFER_ALIGNMENT),
+            active: Vec::new(),
+            closed: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl StagingBelt {
+    /// Records a copy of `size` bytes into `target` at `offset` and returns the memory it
+    /// copies from, which has to be filled before the encoder's command buffer is submitted.
+    /// `target` needs `BufferUsages::COPY_DST`, the write has to fit into it.
+    pub fn write_buffer(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        target: &Buffer,
+        offset: vt::BufferAddress,
+        size: vt::BufferAddress,
+    ) -> Result<&mut [u8], DeviceError> {
+        if !write_fits(offset, size, target.size) {
+            return Err(DeviceError::OutOfBounds {
+                offset,
+                size,
+                buffer_size: target.size,
+            });
+        }
+        if size == 0 {
+            return Ok(&mut []);
+        }
+
+        let index = match self.active.iter().position(|chunk| chunk.space.fits(size)) {
+            Some(index) => index,
+            None => {
+                let chunk = match find_free(self.free.iter().map(|chunk| &chunk.space), size) {
+                    Some(index) => self.free.swap_remove(index),
+                    None => self.create_chunk(size)?,
+                };
+                self.active.push(chunk);
+                self.active.len() - 1
+            }
+        };
+
+        let chunk = &mut self.active[index];
+        let chunk_offset = chunk.space.allocate(size);
+
+        encoder.copy_buffer_to_buffer(&chunk.buffer, chunk_offset, target, offset, size);
+
+        Ok(unsafe {
+            slice::from_raw_parts_mut(chunk.ptr.as_ptr().add(chunk_offset as usize), size as usize)
+        })
+    }
+
+    /// Closes the chunks written since the last call, they are reused once `signal` is
+    /// reached. Call it after the writes, before the submission that copies from them.
+    pub fn finish(&mut self, signal: FrameSignal) -> Result<(), DeviceError> {
+        if self.active.is_empty() {
+            return Ok(());
+        }
+
+        let ranges = self
+            .active
+            .iter()
+            .filter_map(|chunk| {
+                let block = chunk.buffer.block.lock();
+                let block = block.as_ref()?;
+                let coherent = block
+                    .props()
+                    .contains(gpu_alloc::MemoryPropertyFlags::HOST_COHERENT);
+                // blocks are padded to the non-coherent atom size, flush them whole
+                (!coherent).then(|| {
+                    vk::MappedMemoryRange::builder()
+                        .memory(*block.memory())
+                        .offset(block.offset())
+                        .size(block.size())
+                        .build()
+                })
+            })
+            .collect::<Vec<_>>();
+        if !ranges.is_empty() {
+            unsafe {
+                self.device
+                    .handle
+                    .flush_mapped_memory_ranges(&ranges)
+                    .map_err(|err| self.device.map_err(err, None))?;
+            }
+        }
+
+        self.closed.push((signal, std::mem::take(&mut self.active)));
+        Ok(())
+    }
+
+    /// Moves the closed chunks whose signal was reached back into the ring, once per frame
+    /// after waiting for an earlier frame.
+    pub fn recall(&mut self) -> Result<(), DeviceError> {
+        let mut index = 0;
+        while index < self.closed.len() {
+            let reached = match &self.closed[index].0 {
+                FrameSignal::Fence(fence) => unsafe {
+                    self.device
+                        .handle
+                        .get_fence_status(fence.handle)
+                        .map_err(|err| self.device.map_err(err, None))?
+                },
+                FrameSignal::Timeline(semaphore, value) => unsafe {
+                    self.device
+                        .handle
+                        .get_semaphore_counter_value(semaphore.handle)
+                        .map_err(|err| self.device.map_err(err, None))?
+                        >= *value
+                },
+            };
+
+            if reached {
+                let (_, chunks) = self.closed.swap_remove(index);
+                self.free.extend(chunks.into_iter().map(|mut chunk| {
+                    chunk.space = ChunkSpace::new(chunk.space.size);
+                    chunk
+                }));
+            } else {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_chunk(&self, size: vt::BufferAddress) -> Result<Chunk, DeviceError> {
+        // too large to allocate anyway
+        let size = new_chunk_size(self.chunk_size, size)
+            .ok_or(DeviceError::Allocation(AllocationError::OutOfDeviceMemory))?;
+        let buffer = self.device.create_buffer(
+            &BufferInitInfo {
+                label: Some("staging belt chunk"),
+                contents: &[],
+                usage: vt::BufferUsages::MAP_WRITE | vt::BufferUsages::COPY_SRC,
+                sharing: vt::SharingMode::Exclusive,
+                queue_families: &[],
+                location: vt::MemoryLocation::CpuToGpu,
+                memory_priority: None,
+            },
+            size,
+        )?;
+
+        let ptr = unsafe {
+            buffer
+                .block
+                .lock()
+                .as_mut()
+                .unwrap()
+                .map(AshMemoryDevice::wrap(&self.device.handle), 0, size as usize)
+                .map_err(|err| DeviceError::Map(err.into()))?
+        };
+
+        Ok(Chunk {
+            buffer,
+            ptr,
+            space: ChunkSpace::new(size),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_are_aligned_and_fit() {
+        let mut space = ChunkSpace::new(64);
+        assert_eq!(space.allocate(6), 0);
+        assert_eq!(space.allocate(4), 8);
+        assert!(space.fits(52));
+        assert!(!space.fits(53));
+        assert_eq!(space.allocate(52), 12);
+        assert!(!space.fits(1));
+    }
+
+    #[test]
+    fn overflowing_writes_dont_fit() {
+        let mut space = ChunkSpace::new(64);
+        space.allocate(6);
+        assert!(!space.fits(u64::MAX));
+        assert!(!space.fits(u64::MAX - 7));
+    }
+
+    #[test]
+    fn writes_stay_inside_the_target() {
+        assert!(write_fits(0, 64, 64));
+        assert!(write_fits(64, 0, 64));
+        assert!(!write_fits(60, 8, 64));
+        assert!(!write_fits(65, 0, 64));
+        assert!(!write_fits(8, u64::MAX, 64));
+    }
+
+    #[test]
+    fn smallest_free_chunk_is_reused() {
+        let free = [
+            ChunkSpace::new(256),
+            ChunkSpace::new(64),
+            ChunkSpace::new(128),
+        ];
+        assert_eq!(find_free(&free, 16), Some(1));
+        assert_eq!(find_free(&free, 100), Some(2));
+        assert_eq!(find_free(&free, 256), Some(0));
+        assert_eq!(find_free(&free, 257), None);
+        assert_eq!(find_free(&[], 1), None);
+    }
+
+    #[test]
+    fn large_writes_get_their_own_chunk() {
+        assert_eq!(new_chunk_size(1024, 16), Some(1024));
+        assert_eq!(new_chunk_size(1024, 1024), Some(1024));
+        assert_eq!(new_chunk_size(1024, 1025), Some(1028));
+        assert_eq!(new_chunk_size(4, 4096), Some(4096));
+        assert_eq!(new_chunk_size(1024, u64::MAX), None);
+    }
+}
@@ -4,10 +4,12 @@ use tracing::{info, warn};
 
 use crate::adapter::Adapter;
 use crate::conv;
+use crate::deferred::{Deferred, LastUse};
 use crate::queue::Queue;
 use crate::sync::{BinarySemaphore, Fence};
-use crate::texture::{Texture, TextureView};
+use crate::texture::{Texture, TextureState, TextureView};
 use ash::{extensions::khr, vk};
+use parking_lot::Mutex;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use std::sync::Arc;
 use std::{error, fmt};
@@ -19,6 +21,8 @@ pub struct Surface {
     pub(crate) loader: khr::Surface,
     pub(crate) instance: Arc<InstanceShared>,
     pub(crate) swapchain: Option<Swapchain>,
+    /// Device of the last `configure`, which may still hold retired swapchains.
+    pub(crate) device: Option<Arc<DeviceShared>>,
 }
 
 #[derive(Clone)]
@@ -29,6 +33,22 @@ pub struct Swapchain {
     pub(crate) config: SurfaceConfig,
     pub(crate) images: Vec<vk::Image>,
     pub(crate) image_views: Vec<vk::ImageView>,
+    /// Shared by the textures of all frames.
+    pub(crate) last_use: Arc<LastUse>,
+}
+
+impl Swapchain {
+    fn retire(&self, surface: vk::SurfaceKHR) -> (Arc<LastUse>, Deferred) {
+        (
+            self.last_use.clone(),
+            Deferred::Swapchain {
+                loader: self.loader.clone(),
+                handle: self.handle,
+                views: self.image_views.clone(),
+                surface,
+            },
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -43,6 +63,10 @@ impl Frame {
     pub fn view(&self) -> &TextureView {
         &self.view
     }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
 }
 
 impl Surface {
@@ -73,6 +97,9 @@ impl Surface {
             }
             Some(sc) => sc,
         };
+        if sc.device.check_lost().is_err() {
+            return Err(SurfaceError::DeviceLost);
+        }
         let timout = timeout_ms * 1_000_000;
 
         let vk_semaphore = match semaphore {
@@ -93,6 +120,10 @@ impl Surface {
                 Err(err) => {
                     return match err {
                         vk::Result::TIMEOUT => Ok(None),
+                        vk::Result::ERROR_DEVICE_LOST => {
+                            sc.device.map_err(err, None);
+                            Err(SurfaceError::DeviceLost)
+                        }
                         vk::Result::NOT_READY | vk::Result::ERROR_OUT_OF_DATE_KHR => {
                             Err(SurfaceError::Other(err))
                         }
@@ -102,14 +133,33 @@ impl Surface {
             }
         };
 
+        let state = Arc::new(Mutex::new(TextureState::new(1, 1)));
+
         let texture = Frame {
             texture: Texture {
+                device: sc.device.clone(),
                 handle: sc.images[index as usize],
                 usage: sc.config.usage,
+                sharing: vt::SharingMode::Exclusive,
                 block: None,
+                samples: 1,
+                state: state.clone(),
+                last_use: sc.last_use.clone(),
             },
             view: TextureView {
                 handle: sc.image_views[index as usize],
+                image: sc.images[index as usize],
+                format: sc.config.format,
+                samples: 1,
+                range: vt::ImageSubresourceRange {
+                    aspects: vt::TextureAspects::COLOR,
+                    base_mip_level: 0,
+                    mip_level_count: 1,
+                    base_array_layer: 0,
+                    array_layer_count: 1,
+                },
+                state,
+                last_use: sc.last_use.clone(),
             },
             suboptimal,
             index,
@@ -147,10 +197,8 @@ impl Surface {
         device: &Device,
         config: &SurfaceConfig,
     ) -> Result<(), SurfaceError> {
-        let old = self
-            .swapchain
-            .take()
-            .map(|sc| unsafe { sc.release_resources(&device.shared.handle) });
+        let old = self.swapchain.take();
+        self.device = Some(device.shared.clone());
         let swapchain = device.create_swapchain(self, config, old)?;
         self.swapchain = Some(swapchain);
         Ok(())
@@ -166,13 +214,6 @@ impl Surface {
 }
 
 impl Swapchain {
-    unsafe fn release_resources(self, device: &ash::Device) -> Self {
-        {
-            let _ = device.device_wait_idle();
-        };
-        self
-    }
-
     pub fn handle(&self) -> vk::SwapchainKHR {
         self.handle
     }
@@ -205,6 +246,10 @@ impl Frame {
                 sc.loader
                     .queue_present(*handle, &present_info)
                     .map_err(|error| match error {
+                        vk::Result::ERROR_DEVICE_LOST => {
+                            sc.device.map_err(error, Some(*handle));
+                            SurfaceError::DeviceLost
+                        }
                         vk::Result::ERROR_OUT_OF_DATE_KHR => SurfaceError::Other(error),
                         _ => SurfaceError::Other(error),
                     })
@@ -224,7 +269,7 @@ impl Device {
         let loader = khr::Swapchain::new(&surface.instance.handle, &self.shared.handle);
         let device = self.shared.clone();
 
-        let old = match old_sc {
+        let old = match &old_sc {
             Some(osc) => osc.handle,
             None => vk::SwapchainKHR::null(),
         };
@@ -283,8 +328,10 @@ impl Device {
         let result =
             unsafe { loader.create_swapchain(&sc_info, None) }.map_err(SurfaceError::Other);
 
-        if old != vk::SwapchainKHR::null() {
-            unsafe { loader.destroy_swapchain(old, None) }
+        // frames of the old swapchain may still be rendered to
+        if let Some(old_sc) = old_sc {
+            let (last_use, retired) = old_sc.retire(surface.handle);
+            self.shared.defer_used(last_use, retired);
         }
 
         let handle = match result {
@@ -325,6 +372,7 @@ impl Device {
             config: *config,
             images,
             image_views,
+            last_use: Arc::default(),
         })
     }
 }
@@ -349,6 +397,7 @@ impl Instance {
             loader,
             instance,
             swapchain: None,
+            device: None,
         })
     }
 }
@@ -364,14 +413,12 @@ pub struct SurfaceConfig {
 
 impl Drop for Surface {
     fn drop(&mut self) {
+        if let Some(device) = &self.device {
+            let current = self.swapchain.as_ref().map(|sc| sc.retire(self.handle));
+            device.destroy_swapchains(self.handle, current);
+            info!("Destroyed: Swapchain")
+        }
         unsafe {
-            if let Some(sc) = &mut self.swapchain {
-                {
-                    let _ = sc.device.handle.device_wait_idle();
-                }
-                sc.loader.destroy_swapchain(sc.handle, None);
-                info!("Destroyed: Swapchain")
-            }
             self.loader.destroy_surface(self.handle, None);
             info!("Destroyed: Surface")
         }
@@ -380,6 +427,8 @@ impl Drop for Surface {
 
 #[derive(Debug)]
 pub enum SurfaceError {
+    /// The device was lost, see `Device::is_lost`.
+    DeviceLost,
     Other(vk::Result),
 }
 
@@ -1,3 +1,4 @@
+use crate::deferred::Deferred;
 use crate::device::{Device, DeviceError, DeviceShared};
 use ash::vk;
 use std::sync::Arc;
@@ -31,23 +32,25 @@ impl Fence {
     }
 
     pub fn wait(&self, timeout_ms: u64) -> Result<(), DeviceError> {
+        self.device.check_lost()?;
         let timout = timeout_ms * 1_000_000;
         let fences = [self.handle];
         unsafe {
             self.device
                 .handle
                 .wait_for_fences(&fences, true, timout)
-                .map_err(DeviceError::Other)
+                .map_err(|err| self.device.map_err(err, None))
         }
     }
 
     pub fn reset(&self) -> Result<(), DeviceError> {
+        self.device.check_lost()?;
         let fences = [self.handle];
         unsafe {
             self.device
                 .handle
                 .reset_fences(&fences)
-                .map_err(DeviceError::Other)
+                .map_err(|err| self.device.map_err(err, None))
         }
     }
 
@@ -105,11 +108,12 @@ impl TimelineSemaphore {
             self.device
                 .handle
                 .get_semaphore_counter_value(self.handle)
-                .map_err(|err| DeviceError::Other(err))
+                .map_err(|err| self.device.map_err(err, None))
         }
     }
 
     pub fn wait_for_value(&self, value: u64, timout_ms: u64) -> Result<(), DeviceError> {
+        self.device.check_lost()?;
         let semaphores = &[self.handle];
         let values = &[value];
 
@@ -121,45 +125,64 @@ impl TimelineSemaphore {
             self.device
                 .handle
                 .wait_semaphores(&info, timout_ms)
-                .map_err(DeviceError::Other)
+                .map_err(|err| self.device.map_err(err, None))
         }
     }
 }
 
 impl Device {
     pub fn create_binary_semaphore(&self) -> BinarySemaphore {
-        BinarySemaphore::new(&self)
+        BinarySemaphore::new(self)
+    }
+
+    /// Same as `create_binary_semaphore`, with a name shown by debuggers.
+    pub fn create_binary_semaphore_with_label(&self, label: &str) -> BinarySemaphore {
+        let semaphore = BinarySemaphore::new(self);
+        self.shared.set_object_name(semaphore.handle, label);
+        semaphore
     }
 
     pub fn create_timeline_semaphore(&self, initial_value: u64) -> TimelineSemaphore {
-        TimelineSemaphore::new(&self, initial_value)
+        TimelineSemaphore::new(self, initial_value)
+    }
+
+    /// Same as `create_timeline_semaphore`, with a name shown by debuggers.
+    pub fn create_timeline_semaphore_with_label(
+        &self,
+        initial_value: u64,
+        label: &str,
+    ) -> TimelineSemaphore {
+        let semaphore = TimelineSemaphore::new(self, initial_value);
+        self.shared.set_object_name(semaphore.handle, label);
+        semaphore
     }
 
     pub fn create_fence(&self) -> Fence {
-        Fence::new(&self)
+        Fence::new(self)
+    }
+
+    /// Same as `create_fence`, with a name shown by debuggers.
+    pub fn create_fence_with_label(&self, label: &str) -> Fence {
+        let fence = Fence::new(self);
+        self.shared.set_object_name(fence.handle, label);
+        fence
     }
 }
 
 impl Drop for BinarySemaphore {
     fn drop(&mut self) {
-        unsafe { self.device.handle.destroy_semaphore(self.handle, None) }
+        self.device.defer(Deferred::Semaphore(self.handle));
     }
 }
 
 impl Drop for TimelineSemaphore {
     fn drop(&mut self) {
-        unsafe { self.device.handle.destroy_semaphore(self.handle, None) }
+        self.device.defer(Deferred::Semaphore(self.handle));
     }
 }
 
 impl Drop for Fence {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .handle
-                .wait_for_fences(&[self.handle], true, 100000000)
-                .unwrap();
-            self.device.handle.destroy_fence(self.handle, None)
-        }
+        self.device.defer(Deferred::Fence(self.handle));
     }
 }
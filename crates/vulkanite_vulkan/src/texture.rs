@@ -1,24 +1,295 @@
 use crate::conv;
-use crate::device::{Device, DeviceError};
+use crate::deferred::{Deferred, LastUse};
+use crate::device::{Device, DeviceError, DeviceShared};
+use crate::memory::MemoryOwner;
 use crate::pipeline::vt;
 use ash::vk;
-use gpu_alloc_ash::AshMemoryDevice;
+use parking_lot::Mutex;
+use std::sync::Arc;
 
+/// Destroyed once the submissions using it have finished,
+/// swapchain textures are owned by their swapchain.
 #[derive(Debug)]
 pub struct Texture {
+    pub(crate) device: Arc<DeviceShared>,
     pub(crate) handle: vk::Image,
     pub(crate) usage: vt::TextureUsages,
+    pub(crate) sharing: vt::SharingMode,
     pub(crate) block: Option<gpu_alloc::MemoryBlock<vk::DeviceMemory>>,
+    pub(crate) samples: u32,
+    pub(crate) state: Arc<Mutex<TextureState>>,
+    /// Shared with its views, and with the swapchain for swapchain textures.
+    pub(crate) last_use: Arc<LastUse>,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Texture {
+    pub fn mip_levels(&self) -> u32 {
+        self.state.lock().mip_levels
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.state.lock().array_layers
+    }
+
+    /// Current use of a single subresource, as last recorded by `CommandEncoder::transition`.
+    pub fn current_use(&self, mip_level: u32, array_layer: u32) -> vt::TextureUses {
+        let state = self.state.lock();
+        state.uses[state.index(mip_level, array_layer)]
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        if let Some(block) = self.block.take() {
+            self.device
+                .defer_used(self.last_use.clone(), Deferred::Texture(self.handle, block));
+        }
+    }
+}
+
+/// Last recorded use of every (mip level, array layer) of a texture.
+///
+/// Encoders without tracking update the state when a transition is recorded, not when it
+/// executes, so their command buffers have to be submitted in recording order.
+/// Tracking encoders only update it on `Queue::submit`.
+#[derive(Debug)]
+pub(crate) struct TextureState {
+    pub(crate) mip_levels: u32,
+    pub(crate) array_layers: u32,
+    pub(crate) uses: Vec<vt::TextureUses>,
+}
+
+impl TextureState {
+    pub(crate) fn new(mip_levels: u32, array_layers: u32) -> Self {
+        Self {
+            mip_levels,
+            array_layers,
+            uses: vec![vt::TextureUses::UNINITIALIZED; (mip_levels * array_layers) as usize],
+        }
+    }
+
+    pub(crate) fn index(&self, mip_level: u32, array_layer: u32) -> usize {
+        (mip_level * self.array_layers + array_layer) as usize
+    }
+
+    /// Sets every subresource in `range` to `usage`.
+    /// Returns the previous uses, merged into runs of subresources that were in the same state.
+    pub(crate) fn transition(
+        &mut self,
+        range: vt::ImageSubresourceRange,
+        usage: vt::TextureUses,
+    ) -> Vec<(vt::ImageSubresourceRange, vt::TextureUses)> {
+        let array_layers = self.array_layers;
+        subresource_runs(range, self.mip_levels, array_layers, |mip, layer| {
+            let index = (mip * array_layers + layer) as usize;
+            std::mem::replace(&mut self.uses[index], usage)
+        })
+    }
+}
+
+/// Calls `f` for every subresource in `range` (clamped to the texture) and merges the results
+/// into runs of layers, and then runs of mips covering the same layers, with equal values.
+pub(crate) fn subresource_runs<T: Copy + PartialEq>(
+    range: vt::ImageSubresourceRange,
+    mip_levels: u32,
+    array_layers: u32,
+    mut f: impl FnMut(u32, u32) -> T,
+) -> Vec<(vt::ImageSubresourceRange, T)> {
+    // `REMAINING_*` counts, and any other count running past the texture, end at its last
+    // mip or layer
+    let end = |base: u32, count: u32, total: u32| {
+        base.checked_add(count).map_or(total, |end| end.min(total))
+    };
+    let mip_end = end(range.base_mip_level, range.mip_level_count, mip_levels);
+    let layer_end = end(
+        range.base_array_layer,
+        range.array_layer_count,
+        array_layers,
+    );
+
+    let mut runs: Vec<(vt::ImageSubresourceRange, T)> = Vec::new();
+
+    for mip in range.base_mip_level..mip_end {
+        for layer in range.base_array_layer..layer_end {
+            let value = f(mip, layer);
+
+            match runs.last_mut() {
+                Some((run, run_value))
+                    if *run_value == value
+                        && run.base_mip_level + run.mip_level_count - 1 == mip
+                        && run.base_array_layer + run.array_layer_count == layer =>
+                {
+                    run.array_layer_count += 1;
+                }
+                _ => runs.push((
+                    vt::ImageSubresourceRange {
+                        aspects: range.aspects,
+                        base_mip_level: mip,
+                        mip_level_count: 1,
+                        base_array_layer: layer,
+                        array_layer_count: 1,
+                    },
+                    value,
+                )),
+            }
+        }
+    }
+
+    // merge runs of consecutive mips that cover the same layers
+    let mut merged: Vec<(vt::ImageSubresourceRange, T)> = Vec::with_capacity(runs.len());
+    for (run, value) in runs {
+        match merged.last_mut() {
+            Some((previous, previous_value))
+                if *previous_value == value
+                    && previous.base_array_layer == run.base_array_layer
+                    && previous.array_layer_count == run.array_layer_count
+                    && previous.base_mip_level + previous.mip_level_count == run.base_mip_level =>
+            {
+                previous.mip_level_count += 1;
+            }
+            _ => merged.push((run, value)),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(
+        mips: std::ops::Range<u32>,
+        layers: std::ops::Range<u32>,
+    ) -> vt::ImageSubresourceRange {
+        vt::ImageSubresourceRange {
+            aspects: vt::TextureAspects::COLOR,
+            base_mip_level: mips.start,
+            mip_level_count: mips.end - mips.start,
+            base_array_layer: layers.start,
+            array_layer_count: layers.end - layers.start,
+        }
+    }
+
+    /// `(mips, layers)` of every run.
+    fn bounds<T>(runs: &[(vt::ImageSubresourceRange, T)]) -> Vec<((u32, u32), (u32, u32))> {
+        runs.iter()
+            .map(|(run, _)| {
+                (
+                    (run.base_mip_level, run.base_mip_level + run.mip_level_count),
+                    (
+                        run.base_array_layer,
+                        run.base_array_layer + run.array_layer_count,
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn whole_texture_is_one_run() {
+        let runs = subresource_runs(range(0..4, 0..6), 4, 6, |_, _| 0);
+        assert_eq!(bounds(&runs), [((0, 4), (0, 6))]);
+    }
+
+    #[test]
+    fn partial_range() {
+        let runs = subresource_runs(range(1..3, 2..5), 4, 6, |_, _| 0);
+        assert_eq!(bounds(&runs), [((1, 3), (2, 5))]);
+    }
+
+    #[test]
+    fn range_is_clamped_to_the_texture() {
+        let runs = subresource_runs(range(2..8, 4..9), 4, 6, |_, _| 0);
+        assert_eq!(bounds(&runs), [((2, 4), (4, 6))]);
+
+        let runs = subresource_runs(range(5..6, 0..1), 4, 6, |_, _| 0);
+        assert!(runs.is_empty());
+    }
+
+    #[test]
+    fn remaining_counts() {
+        let remaining = vt::ImageSubresourceRange {
+            aspects: vt::TextureAspects::COLOR,
+            base_mip_level: 1,
+            mip_level_count: vt::REMAINING_MIP_LEVELS,
+            base_array_layer: 3,
+            array_layer_count: vt::REMAINING_ARRAY_LAYERS,
+        };
+        let runs = subresource_runs(remaining, 4, 6, |_, _| 0);
+        assert_eq!(bounds(&runs), [((1, 4), (3, 6))]);
+    }
+
+    #[test]
+    fn runs_split_on_different_values() {
+        // layer 1 of mip 1 differs
+        let runs = subresource_runs(range(0..3, 0..3), 3, 3, |mip, layer| mip == 1 && layer == 1);
+        assert_eq!(
+            runs.iter().map(|(_, value)| *value).collect::<Vec<_>>(),
+            [false, false, true, false, false]
+        );
+        assert_eq!(
+            bounds(&runs),
+            [
+                ((0, 1), (0, 3)),
+                ((1, 2), (0, 1)),
+                ((1, 2), (1, 2)),
+                ((1, 2), (2, 3)),
+                ((2, 3), (0, 3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_transitions() {
+        let mut state = TextureState::new(3, 4);
+        let old = state.transition(range(0..2, 0..2), vt::TextureUses::COPY_DST);
+        assert_eq!(bounds(&old), [((0, 2), (0, 2))]);
+        assert_eq!(old[0].1, vt::TextureUses::UNINITIALIZED);
+
+        // overlaps the first transition in mips 1..2 and layers 1..2
+        let old = state.transition(range(1..3, 1..4), vt::TextureUses::RESOURCE);
+        assert_eq!(
+            old.iter().map(|(_, usage)| *usage).collect::<Vec<_>>(),
+            [
+                vt::TextureUses::COPY_DST,
+                vt::TextureUses::UNINITIALIZED,
+                vt::TextureUses::UNINITIALIZED,
+            ]
+        );
+        assert_eq!(
+            bounds(&old),
+            [((1, 2), (1, 2)), ((1, 2), (2, 4)), ((2, 3), (1, 4))]
+        );
+
+        assert_eq!(state.uses[state.index(0, 0)], vt::TextureUses::COPY_DST);
+        assert_eq!(state.uses[state.index(1, 0)], vt::TextureUses::COPY_DST);
+        assert_eq!(state.uses[state.index(1, 1)], vt::TextureUses::RESOURCE);
+        assert_eq!(state.uses[state.index(2, 3)], vt::TextureUses::RESOURCE);
+        assert_eq!(
+            state.uses[state.index(2, 0)],
+            vt::TextureUses::UNINITIALIZED
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TextureView {
     pub(crate) handle: vk::ImageView,
+    pub(crate) image: vk::Image,
+    pub(crate) format: vt::TextureFormat,
+    pub(crate) samples: u32,
+    pub(crate) range: vt::ImageSubresourceRange,
+    pub(crate) state: Arc<Mutex<TextureState>>,
+    pub(crate) last_use: Arc<LastUse>,
 }
 
 impl Device {
-    pub fn create_texture(&self, info: &vt::TextureInfo) -> Result<Texture, DeviceError> {
-        let vk_info = vk::ImageCreateInfo::builder()
+    pub fn create_texture(&self, info: &vt::TextureInfo<'_>) -> Result<Texture, DeviceError> {
+        self.shared.check_lost()?;
+        self.validate_sharing(info.sharing, info.queue_families)?;
+
+        let mut vk_info = vk::ImageCreateInfo::builder()
             .image_type(conv::map_texture_dimension(info.dimension))
             .format(conv::map_texture_format(info.format))
             .mip_levels(info.mip_levels)
@@ -30,6 +301,10 @@ impl Device {
             .sharing_mode(conv::map_sharing_mode(info.sharing))
             .initial_layout(vk::ImageLayout::UNDEFINED);
 
+        if let vt::SharingMode::Concurrent = info.sharing {
+            vk_info = vk_info.queue_family_indices(info.queue_families);
+        }
+
         let handle = unsafe {
             self.shared
                 .handle
@@ -37,52 +312,87 @@ impl Device {
                 .map_err(DeviceError::Other)?
         };
 
-        let requirements = unsafe { self.shared.handle.get_image_memory_requirements(handle) };
+        if let Some(label) = info.label {
+            self.shared.set_object_name(handle, label);
+        }
+
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements =
+            vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        unsafe {
+            self.shared.handle.get_image_memory_requirements2(
+                &vk::ImageMemoryRequirementsInfo2::builder().image(handle),
+                &mut requirements,
+            );
+        }
+        let requirements = requirements.memory_requirements;
+
+        let transient_attachment = info.usage.contains(vt::TextureUsages::TRANSIENT_ATTACHMENT);
+        let location = match info.location {
+            vt::MemoryLocation::Auto if transient_attachment => vt::MemoryLocation::Transient,
+            location => location,
+        };
+
+        let priority = info.memory_priority.filter(|_| self.shared.memory_priority);
+
+        let dedicated = dedicated_requirements.requires_dedicated_allocation == vk::TRUE
+            || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE;
 
         let block = unsafe {
-            self.allocator
-                .lock()
-                .alloc(
-                    AshMemoryDevice::wrap(self.raw()),
-                    gpu_alloc::Request {
-                        size: requirements.size,
-                        align_mask: requirements.alignment - 1,
-                        usage: gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
-                        memory_types: requirements.memory_type_bits,
-                    },
+            let mut allocator = self.shared.allocator.lock();
+            let mut request = allocator.request(&requirements, conv::map_memory_location(location));
+            if location == vt::MemoryLocation::Transient && transient_attachment {
+                request.memory_types = allocator.prefer_lazily_allocated(request.memory_types);
+            }
+
+            allocator
+                .alloc_for(
+                    self.raw(),
+                    request,
+                    MemoryOwner::Image(handle),
+                    dedicated,
+                    priority,
                 )
-                .unwrap()
+                .map_err(|err| {
+                    self.shared.handle.destroy_image(handle, None);
+                    DeviceError::Allocation(err)
+                })?
         };
 
-        unsafe {
+        let result = unsafe {
             self.shared
                 .handle
                 .bind_image_memory(handle, *block.memory(), block.offset())
-                .map_err(DeviceError::Other)?
+        };
+        if let Err(err) = result {
+            unsafe {
+                self.shared.handle.destroy_image(handle, None);
+                self.shared.allocator.lock().dealloc(self.raw(), block);
+            }
+            return Err(DeviceError::Other(err));
         }
 
         Ok(Texture {
+            device: self.shared.clone(),
             handle,
             usage: info.usage,
+            sharing: info.sharing,
             block: Some(block),
+            samples: info.samples,
+            state: Arc::new(Mutex::new(TextureState::new(
+                info.mip_levels,
+                info.size.depth,
+            ))),
+            last_use: Arc::default(),
         })
     }
 
-    pub fn free_texture(&self, texture: Texture) {
-        if let Some(block) = texture.block {
-            unsafe {
-                self.allocator
-                    .lock()
-                    .dealloc(AshMemoryDevice::wrap(&self.shared.handle), block)
-            }
-        }
-    }
-
     pub fn create_texture_view(
         &self,
-        info: &vt::TextureViewInfo,
+        info: &vt::TextureViewInfo<'_>,
         texture: &Texture,
     ) -> Result<TextureView, DeviceError> {
+        self.shared.check_lost()?;
         let vk_info = vk::ImageViewCreateInfo::builder()
             .flags(vk::ImageViewCreateFlags::empty())
             .image(texture.handle)
@@ -97,6 +407,18 @@ impl Device {
                 .map_err(DeviceError::Other)?
         };
 
-        Ok(TextureView { handle })
+        if let Some(label) = info.label {
+            self.shared.set_object_name(handle, label);
+        }
+
+        Ok(TextureView {
+            handle,
+            image: texture.handle,
+            format: info.format,
+            samples: texture.samples,
+            range: info.range,
+            state: texture.state.clone(),
+            last_use: texture.last_use.clone(),
+        })
     }
 }
@@ -0,0 +1,417 @@
+use crate::conv;
+use crate::pipeline::vt;
+use crate::texture::{subresource_runs, TextureState};
+use ash::vk::{self, Handle};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Whether going from `old` to `new` needs a barrier, two read-only uses in the same layout don't.
+pub(crate) fn texture_needs_barrier(old: vt::TextureUses, new: vt::TextureUses) -> bool {
+    old != new || old.intersects(vt::TextureUses::EXCLUSIVE)
+}
+
+/// Buffers have no layout, so only hazards involving a write need a barrier.
+pub(crate) fn buffer_needs_barrier(old: vt::BufferUses, new: vt::BufferUses) -> bool {
+    !old.is_empty() && (old | new).intersects(vt::BufferUses::EXCLUSIVE)
+}
+
+pub(crate) fn image_barrier(
+    image: vk::Image,
+    range: vt::ImageSubresourceRange,
+    old: vt::TextureUses,
+    new: vt::TextureUses,
+) -> vk::ImageMemoryBarrier2 {
+    let (src_stage, src_access) = conv::map_texture_usage_to_barrier(old);
+    let (dst_stage, dst_access) = conv::map_texture_usage_to_barrier(new);
+    vk::ImageMemoryBarrier2::builder()
+        .src_stage_mask(conv::map_stage_flags(src_stage))
+        .src_access_mask(conv::map_access_flags(src_access))
+        .dst_stage_mask(conv::map_stage_flags(dst_stage))
+        .dst_access_mask(conv::map_access_flags(dst_access))
+        .old_layout(conv::derive_image_layout(old, range.aspects))
+        .new_layout(conv::derive_image_layout(new, range.aspects))
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(conv::map_texture_subresource_range(range))
+        .build()
+}
+
+pub(crate) fn buffer_barrier(
+    buffer: vk::Buffer,
+    old: vt::BufferUses,
+    new: vt::BufferUses,
+) -> vk::BufferMemoryBarrier2 {
+    let (src_stage, src_access) = conv::map_buffer_usage_to_barrier(old);
+    let (dst_stage, dst_access) = conv::map_buffer_usage_to_barrier(new);
+    vk::BufferMemoryBarrier2::builder()
+        .src_stage_mask(conv::map_stage_flags(src_stage))
+        .src_access_mask(conv::map_access_flags(src_access))
+        .dst_stage_mask(conv::map_stage_flags(dst_stage))
+        .dst_access_mask(conv::map_access_flags(dst_access))
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .build()
+}
+
+/// Barrier queued until the next flush. The `vk` structs are only built then, they hold a raw
+/// `p_next` pointer and would make the encoder neither `Send` nor `Sync`.
+#[derive(Debug, Clone, Copy)]
+enum PendingBarrier {
+    Image(
+        vk::Image,
+        vt::ImageSubresourceRange,
+        vt::TextureUses,
+        vt::TextureUses,
+    ),
+    Buffer(vk::Buffer, vt::BufferUses, vt::BufferUses),
+}
+
+#[derive(Debug)]
+struct TrackedTexture {
+    state: Arc<Mutex<TextureState>>,
+    aspects: vt::TextureAspects,
+    /// Use each subresource is expected in when the command buffer starts.
+    first: Vec<Option<vt::TextureUses>>,
+    /// Use each subresource is in after the commands recorded so far.
+    current: Vec<Option<vt::TextureUses>>,
+}
+
+#[derive(Debug)]
+struct TrackedBuffer {
+    state: Arc<Mutex<vt::BufferUses>>,
+    first: vt::BufferUses,
+    current: vt::BufferUses,
+}
+
+/// Records the uses of resources within one command buffer.
+///
+/// Barriers between uses inside the command buffer are queued and flushed by the encoder before
+/// the next draw, dispatch or copy. The first use of every resource can't be known until the
+/// command buffer is submitted, `resolve` produces the barriers from the global state to it.
+///
+/// Inside a rendering scope the barriers are recorded ahead of the scope, so a resource can
+/// only have one use in it.
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    textures: HashMap<vk::Image, TrackedTexture>,
+    buffers: HashMap<vk::Buffer, TrackedBuffer>,
+    pending: Vec<PendingBarrier>,
+    /// Raw handles of the resources used in the current rendering scope.
+    scope: Option<HashSet<u64>>,
+}
+
+impl Tracker {
+    pub(crate) fn use_texture(
+        &mut self,
+        image: vk::Image,
+        state: &Arc<Mutex<TextureState>>,
+        range: vt::ImageSubresourceRange,
+        usage: vt::TextureUses,
+    ) {
+        let tracked = self.tracked_texture(image, state, range.aspects);
+
+        let (mip_levels, array_layers) = {
+            let state = tracked.state.lock();
+            (state.mip_levels, state.array_layers)
+        };
+
+        let runs = subresource_runs(range, mip_levels, array_layers, |mip, layer| {
+            let index = (mip * array_layers + layer) as usize;
+            if tracked.first[index].is_none() {
+                tracked.first[index] = Some(usage);
+            }
+            tracked.current[index].replace(usage)
+        });
+
+        for (range, old) in runs {
+            match old {
+                Some(old) if texture_needs_barrier(old, usage) => {
+                    self.check_scope(image.as_raw());
+                    self.pending
+                        .push(PendingBarrier::Image(image, range, old, usage));
+                }
+                _ => {}
+            }
+        }
+        self.add_to_scope(image.as_raw());
+    }
+
+    pub(crate) fn use_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        state: &Arc<Mutex<vt::BufferUses>>,
+        usage: vt::BufferUses,
+    ) {
+        match self.buffers.get_mut(&buffer) {
+            Some(tracked) => {
+                let old = std::mem::replace(&mut tracked.current, usage);
+                if buffer_needs_barrier(old, usage) {
+                    self.check_scope(buffer.as_raw());
+                    self.pending
+                        .push(PendingBarrier::Buffer(buffer, old, usage));
+                }
+            }
+            None => {
+                self.buffers.insert(
+                    buffer,
+                    TrackedBuffer {
+                        state: state.clone(),
+                        first: usage,
+                        current: usage,
+                    },
+                );
+            }
+        }
+        self.add_to_scope(buffer.as_raw());
+    }
+
+    /// Starts a rendering scope whose attachments were just used.
+    pub(crate) fn begin_scope(&mut self, attachments: impl IntoIterator<Item = vk::Image>) {
+        self.scope = Some(
+            attachments
+                .into_iter()
+                .map(|image| image.as_raw())
+                .collect(),
+        );
+    }
+
+    pub(crate) fn end_scope(&mut self) {
+        self.scope = None;
+    }
+
+    /// A barrier for a resource already used in the scope can't be moved ahead of it.
+    fn check_scope(&self, resource: u64) {
+        if self
+            .scope
+            .as_ref()
+            .is_some_and(|scope| scope.contains(&resource))
+        {
+            panic!(
+                "a resource changed its use inside a rendering scope, where it can't have \
+                 barriers, use it the same way throughout the scope"
+            );
+        }
+    }
+
+    fn add_to_scope(&mut self, resource: u64) {
+        if let Some(scope) = &mut self.scope {
+            scope.insert(resource);
+        }
+    }
+
+    /// Use of every subresource in `range` after the commands recorded so far, merged into runs.
+    /// Subresources not used yet in the command buffer start out in their global use, which is
+    /// checked against the global state again by `resolve`.
+    pub(crate) fn current_texture_uses(
+        &mut self,
+        image: vk::Image,
+        state: &Arc<Mutex<TextureState>>,
+        range: vt::ImageSubresourceRange,
+    ) -> Vec<(vt::ImageSubresourceRange, vt::TextureUses)> {
+        let tracked = self.tracked_texture(image, state, range.aspects);
+        let state = state.lock();
+        let array_layers = state.array_layers;
+
+        subresource_runs(range, state.mip_levels, array_layers, |mip, layer| {
+            let index = (mip * array_layers + layer) as usize;
+            *tracked.current[index].get_or_insert_with(|| {
+                tracked.first[index] = Some(state.uses[index]);
+                state.uses[index]
+            })
+        })
+    }
+
+    /// Use of `buffer` after the commands recorded so far, see `current_texture_uses`.
+    pub(crate) fn current_buffer_use(
+        &mut self,
+        buffer: vk::Buffer,
+        state: &Arc<Mutex<vt::BufferUses>>,
+    ) -> vt::BufferUses {
+        self.buffers
+            .entry(buffer)
+            .or_insert_with(|| {
+                let usage = *state.lock();
+                TrackedBuffer {
+                    state: state.clone(),
+                    first: usage,
+                    current: usage,
+                }
+            })
+            .current
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub(crate) fn take_pending(
+        &mut self,
+    ) -> (Vec<vk::ImageMemoryBarrier2>, Vec<vk::BufferMemoryBarrier2>) {
+        let mut image_barriers = Vec::new();
+        let mut buffer_barriers = Vec::new();
+        for barrier in self.pending.drain(..) {
+            match barrier {
+                PendingBarrier::Image(image, range, old, new) => {
+                    image_barriers.push(image_barrier(image, range, old, new))
+                }
+                PendingBarrier::Buffer(buffer, old, new) => {
+                    buffer_barriers.push(buffer_barrier(buffer, old, new))
+                }
+            }
+        }
+        (image_barriers, buffer_barriers)
+    }
+
+    fn tracked_texture(
+        &mut self,
+        image: vk::Image,
+        state: &Arc<Mutex<TextureState>>,
+        aspects: vt::TextureAspects,
+    ) -> &mut TrackedTexture {
+        self.textures.entry(image).or_insert_with(|| {
+            let (mip_levels, array_layers) = {
+                let state = state.lock();
+                (state.mip_levels, state.array_layers)
+            };
+            let count = (mip_levels * array_layers) as usize;
+            TrackedTexture {
+                state: state.clone(),
+                aspects,
+                first: vec![None; count],
+                current: vec![None; count],
+            }
+        })
+    }
+
+    /// Barriers from the global state of every resource to its first use in the command buffer.
+    /// Moves the global state to the last use, so this must happen in submission order.
+    pub(crate) fn resolve(self) -> (Vec<vk::ImageMemoryBarrier2>, Vec<vk::BufferMemoryBarrier2>) {
+        let mut image_barriers = Vec::new();
+        let mut buffer_barriers = Vec::new();
+
+        for (image, tracked) in self.textures {
+            let mut state = tracked.state.lock();
+            let range = vt::ImageSubresourceRange {
+                aspects: tracked.aspects,
+                base_mip_level: 0,
+                mip_level_count: state.mip_levels,
+                base_array_layer: 0,
+                array_layer_count: state.array_layers,
+            };
+            let (mip_levels, array_layers) = (state.mip_levels, state.array_layers);
+
+            let runs = subresource_runs(range, mip_levels, array_layers, |mip, layer| {
+                let index = (mip * array_layers + layer) as usize;
+                let old = state.uses[index];
+                if let Some(current) = tracked.current[index] {
+                    state.uses[index] = current;
+                }
+                (old, tracked.first[index])
+            });
+
+            for (range, (old, first)) in runs {
+                match first {
+                    Some(first) if texture_needs_barrier(old, first) => {
+                        image_barriers.push(image_barrier(image, range, old, first));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (buffer, tracked) in self.buffers {
+            let mut state = tracked.state.lock();
+            if buffer_needs_barrier(*state, tracked.first) {
+                buffer_barriers.push(buffer_barrier(buffer, *state, tracked.first));
+            }
+            *state = tracked.current;
+        }
+
+        (image_barriers, buffer_barriers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn current_buffer_use() {
+        let buffer = vk::Buffer::from_raw(1);
+        let state = Arc::new(Mutex::new(vt::BufferUses::UNIFORM));
+        let mut tracker = Tracker::default();
+
+        // untracked buffers start in their global use
+        assert_eq!(
+            tracker.current_buffer_use(buffer, &state),
+            vt::BufferUses::UNIFORM
+        );
+
+        tracker.use_buffer(buffer, &state, vt::BufferUses::STORAGE_READ_WRITE);
+        assert_eq!(
+            tracker.current_buffer_use(buffer, &state),
+            vt::BufferUses::STORAGE_READ_WRITE
+        );
+
+        let (_, buffer_barriers) = tracker.resolve();
+        assert!(buffer_barriers.is_empty());
+        assert_eq!(*state.lock(), vt::BufferUses::STORAGE_READ_WRITE);
+    }
+
+    #[test]
+    fn scope_barriers_are_queued() {
+        let buffer = vk::Buffer::from_raw(1);
+        let state = Arc::new(Mutex::new(vt::BufferUses::empty()));
+        let mut tracker = Tracker::default();
+
+        tracker.use_buffer(buffer, &state, vt::BufferUses::COPY_DST);
+        tracker.begin_scope([vk::Image::from_raw(2)]);
+        tracker.use_buffer(buffer, &state, vt::BufferUses::VERTEX);
+        // the same use again needs no barrier
+        tracker.use_buffer(buffer, &state, vt::BufferUses::VERTEX);
+        tracker.end_scope();
+
+        let (image_barriers, buffer_barriers) = tracker.take_pending();
+        assert!(image_barriers.is_empty());
+        assert_eq!(buffer_barriers.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "inside a rendering scope")]
+    fn scope_use_changes_panic() {
+        let buffer = vk::Buffer::from_raw(1);
+        let state = Arc::new(Mutex::new(vt::BufferUses::empty()));
+        let mut tracker = Tracker::default();
+
+        tracker.begin_scope([]);
+        tracker.use_buffer(buffer, &state, vt::BufferUses::STORAGE_READ_WRITE);
+        tracker.use_buffer(buffer, &state, vt::BufferUses::VERTEX);
+    }
+
+    #[test]
+    fn current_texture_uses() {
+        let image = vk::Image::from_raw(1);
+        let state = Arc::new(Mutex::new(TextureState::new(2, 1)));
+        let mut tracker = Tracker::default();
+        let range = |base_mip_level, mip_level_count| vt::ImageSubresourceRange {
+            aspects: vt::TextureAspects::COLOR,
+            base_mip_level,
+            mip_level_count,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        };
+
+        tracker.use_texture(image, &state, range(1, 1), vt::TextureUses::COPY_DST);
+        let uses = tracker.current_texture_uses(image, &state, range(0, 2));
+        assert_eq!(
+            uses.iter().map(|(_, usage)| *usage).collect::<Vec<_>>(),
+            [vt::TextureUses::UNINITIALIZED, vt::TextureUses::COPY_DST]
+        );
+    }
+}
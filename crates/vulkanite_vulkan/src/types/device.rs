@@ -358,6 +358,7 @@ pub struct Extensions {
     pub vk_ext_depth_clip_enable: bool,
     pub vk_ext_depth_range_unrestricted: bool,
     pub vk_ext_descriptor_indexing: bool,
+    pub vk_ext_device_fault: bool,
     pub vk_ext_direct_mode_display: bool,
     pub vk_ext_discard_rectangles: bool,
     pub vk_ext_display_control: bool,
@@ -573,6 +574,7 @@ impl Extensions {
             vk_ext_depth_clip_enable: false,
             vk_ext_depth_range_unrestricted: false,
             vk_ext_descriptor_indexing: false,
+            vk_ext_device_fault: false,
             vk_ext_direct_mode_display: false,
             vk_ext_discard_rectangles: false,
             vk_ext_display_control: false,
@@ -903,6 +905,10 @@ impl From<Extensions> for Vec<CString> {
             extensions.push(CString::new(&"VK_EXT_descriptor_indexing"[..]).unwrap());
         }
 
+        if device_extensions.vk_ext_device_fault {
+            extensions.push(CString::new(&"VK_EXT_device_fault"[..]).unwrap());
+        }
+
         if device_extensions.vk_ext_direct_mode_display {
             extensions.push(CString::new(&"VK_EXT_direct_mode_display"[..]).unwrap());
         }
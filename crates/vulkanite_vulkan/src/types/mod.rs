@@ -8,28 +8,56 @@ pub use pipeline::*;
 
 use std::ffi::CString;
 
+/// Instance layers, layers that aren't installed are skipped with a warning.
 #[derive(Debug, Copy, Clone)]
 pub struct Layers {
-    khronos_validation: bool,
+    pub khronos_validation: bool,
+    pub lunarg_api_dump: bool,
+    pub lunarg_monitor: bool,
 }
 
 impl Layers {
     pub fn none() -> Self {
         Self {
             khronos_validation: false,
+            lunarg_api_dump: false,
+            lunarg_monitor: false,
         }
     }
-}
 
-impl From<Layers> for Vec<CString> {
-    fn from(layers: Layers) -> Self {
-        let mut result = vec![];
+    fn entries(&mut self) -> [(&mut bool, &'static str); 3] {
+        [
+            (&mut self.khronos_validation, "VK_LAYER_KHRONOS_validation"),
+            (&mut self.lunarg_api_dump, "VK_LAYER_LUNARG_api_dump"),
+            (&mut self.lunarg_monitor, "VK_LAYER_LUNARG_monitor"),
+        ]
+    }
 
-        if layers.khronos_validation {
-            result.push(CString::new(&"VK_LAYER_KHRONOS_validation"[..]).unwrap())
+    /// Disables the layers missing from `available` and returns their names.
+    pub(crate) fn retain_available(&mut self, available: &[CString]) -> Vec<&'static str> {
+        let mut missing = vec![];
+        for (enabled, name) in self.entries() {
+            if *enabled
+                && !available
+                    .iter()
+                    .any(|layer| layer.to_bytes() == name.as_bytes())
+            {
+                *enabled = false;
+                missing.push(name);
+            }
         }
+        missing
+    }
+}
 
-        result
+impl From<Layers> for Vec<CString> {
+    fn from(mut layers: Layers) -> Self {
+        layers
+            .entries()
+            .into_iter()
+            .filter(|(enabled, _)| **enabled)
+            .map(|(_, name)| CString::new(name).unwrap())
+            .collect()
     }
 }
 
@@ -37,10 +65,27 @@ impl Default for Layers {
     fn default() -> Self {
         Self {
             khronos_validation: true,
+            ..Self::none()
         }
     }
 }
 
+/// Optional checks of the Khronos validation layer, enabled through `VK_EXT_validation_features`.
+/// `gpu_assisted` and `debug_printf` can't be used together, `Instance::new` rejects them.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ValidationFeatures {
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    pub synchronization: bool,
+    pub debug_printf: bool,
+}
+
+impl ValidationFeatures {
+    pub fn is_empty(&self) -> bool {
+        !(self.gpu_assisted || self.best_practices || self.synchronization || self.debug_printf)
+    }
+}
+
 #[cfg(test)]
 mod type_tests {
     use super::*;
@@ -10,6 +10,17 @@ pub struct RenderInfo<'a> {
     pub stencil_attachment: Option<StencilAttachment>,
     pub offset: (i32, i32),
     pub area: (u32, u32),
+    /// The contents are recorded in bundles, only `CommandEncoder::execute_bundles` is allowed
+    /// until `end_rendering`.
+    pub bundles: bool,
+}
+
+/// Attachments of the rendering scopes a bundle is executed in.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderBundleInfo<'a> {
+    pub color_formats: &'a [vt::TextureFormat],
+    pub depth_format: Option<vt::TextureFormat>,
+    pub sample_count: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -0,0 +1,78 @@
+use vulkanite_vulkan::vn;
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+
+#[test]
+fn handles_can_be_shared_with_threads() {
+    assert_send::<vn::Device>();
+    assert_sync::<vn::Device>();
+    assert_send::<vn::CommandEncoder>();
+    assert_send::<vn::CommandBuffer>();
+    assert_send::<vn::RenderBundle>();
+}
+
+/// Device with one queue, `None` when there is no Vulkan implementation.
+fn device() -> Option<(vn::Instance, vn::Device, vn::Queue)> {
+    let instance = match vn::Instance::new(vn::InstanceCreateInfo {
+        render: false,
+        ..vn::InstanceCreateInfo::default()
+    }) {
+        Ok(instance) => instance,
+        Err(err) => {
+            eprintln!("skipped, no Vulkan instance: {}", err);
+            return None;
+        }
+    };
+    let adapter = match instance.adapters().next() {
+        Some(adapter) => adapter,
+        None => {
+            eprintln!("skipped, no adapter");
+            return None;
+        }
+    };
+    let family = adapter.queue_families().next().unwrap();
+    let (device, mut queues) = adapter
+        .request_device(vn::DeviceCreateInfo {
+            queue_families: vec![vn::QueueCreateInfo::new(family, vec![1.0])],
+            ..vn::DeviceCreateInfo::default()
+        })
+        .unwrap();
+    let queue = queues.next().unwrap();
+    Some((instance, device, queue))
+}
+
+#[test]
+fn records_on_two_threads() {
+    let (_instance, device, mut queue) = match device() {
+        Some(device) => device,
+        None => return,
+    };
+
+    let buffers = std::thread::scope(|scope| {
+        let threads = (0..2)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut encoder = device
+                        .command_encoder(vn::CommandEncoderInfo {
+                            label: None,
+                            queue: &queue,
+                            tracking: true,
+                            frames_in_flight: 1,
+                        })
+                        .unwrap();
+                    encoder.begin_encoding();
+                    encoder.finish()
+                })
+            })
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let fence = device.create_fence();
+    queue.submit(buffers, &[], &[], Some(&fence)).unwrap();
+    fence.wait(10_000).unwrap();
+}
@@ -0,0 +1,73 @@
+use vulkanite_vulkan::raw::vk;
+use vulkanite_vulkan::vn;
+
+/// Instance collecting validation messages, `None` when there is no Vulkan implementation or
+/// the validation layer isn't installed.
+fn instance() -> Option<vn::Instance> {
+    let instance = match vn::Instance::new(vn::InstanceCreateInfo {
+        render: false,
+        debug_messenger: vn::DebugMessengerInfo {
+            // keep the expected errors out of the test output
+            callback: Some(std::sync::Arc::new(|_| {})),
+            collect_validation_messages: true,
+            ..vn::DebugMessengerInfo::default()
+        },
+        ..vn::InstanceCreateInfo::default()
+    }) {
+        Ok(instance) => instance,
+        Err(err) => {
+            eprintln!("skipped, no Vulkan instance: {}", err);
+            return None;
+        }
+    };
+
+    if !instance.validation_enabled() {
+        eprintln!("skipped, the validation layer isn't installed");
+        return None;
+    }
+    Some(instance)
+}
+
+#[test]
+fn collects_validation_messages() {
+    let instance = match instance() {
+        Some(instance) => instance,
+        None => return,
+    };
+    let adapter = match instance.adapters().next() {
+        Some(adapter) => adapter,
+        None => {
+            eprintln!("skipped, no adapter");
+            return;
+        }
+    };
+    let family = adapter.queue_families().next().unwrap();
+    let (device, _queues) = adapter
+        .request_device(vn::DeviceCreateInfo {
+            queue_families: vec![vn::QueueCreateInfo::new(family, vec![1.0])],
+            ..vn::DeviceCreateInfo::default()
+        })
+        .unwrap();
+    instance.drain_validation_messages();
+
+    // VUID-VkBufferCreateInfo-size-00912, buffers can't be empty
+    let info = vk::BufferCreateInfo::builder()
+        .size(0)
+        .usage(vk::BufferUsageFlags::TRANSFER_SRC);
+    unsafe {
+        if let Ok(buffer) = device.raw().create_buffer(&info, None) {
+            device.raw().destroy_buffer(buffer, None);
+        }
+    }
+
+    let messages = instance.validation_messages();
+    assert!(
+        messages.iter().any(|message| message
+            .id_name
+            .contains("VUID-VkBufferCreateInfo-size-00912")),
+        "{:?}",
+        messages
+    );
+    assert_eq!(instance.drain_validation_messages().len(), messages.len());
+    assert!(instance.validation_messages().is_empty());
+}
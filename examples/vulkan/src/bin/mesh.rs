@@ -1,5 +1,6 @@
 use std::{io, mem};
 use vulkanite_vulkan::vn;
+use vulkanite_vulkan::vn::VertexLayout;
 
 use nalgebra_glm as na;
 use tracing::info;
@@ -9,7 +10,7 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable, vn::VertexLayout)]
 struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
@@ -18,31 +19,21 @@ struct Vertex {
 
 const DEPTH_FORMAT: vn::TextureFormat = vn::TextureFormat::D32Sfloat;
 
-impl Vertex {
-    pub fn desc<'a>() -> vn::VertexBufferLayout<'a> {
-        vn::VertexBufferLayout {
-            array_stride: mem::size_of::<Vertex>() as vn::BufferAddress,
-            step_mode: vn::VertexStepMode::Vertex,
-            attributes: &[
-                vn::VertexAttribute {
-                    format: vn::VertexFormat::Float32x3,
-                    offset: 0,
-                    location: 0,
-                },
-                vn::VertexAttribute {
-                    format: vn::VertexFormat::Float32x3,
-                    offset: mem::size_of::<[f32; 3]>() as vn::BufferAddress,
-                    location: 1,
-                },
-                vn::VertexAttribute {
-                    format: vn::VertexFormat::Float32x3,
-                    offset: (mem::size_of::<[f32; 3]>() * 2) as vn::BufferAddress,
-                    location: 2,
-                },
-            ],
-        }
-    }
-}
+const COLOR_RANGE: vn::ImageSubresourceRange = vn::ImageSubresourceRange {
+    aspects: vn::TextureAspects::COLOR,
+    base_mip_level: 0,
+    mip_level_count: 1,
+    base_array_layer: 0,
+    array_layer_count: 1,
+};
+
+const DEPTH_RANGE: vn::ImageSubresourceRange = vn::ImageSubresourceRange {
+    aspects: vn::TextureAspects::DEPTH,
+    base_mip_level: 0,
+    mip_level_count: 1,
+    base_array_layer: 0,
+    array_layer_count: 1,
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -165,11 +156,15 @@ fn main() {
             contents: bytemuck::cast_slice(&vertices),
             usage: vn::BufferUsages::VERTEX | vn::BufferUsages::MAP_WRITE,
             sharing: vn::SharingMode::Exclusive,
+            queue_families: &[],
+            location: vn::MemoryLocation::Auto,
+            memory_priority: None,
         })
         .unwrap();
 
     let mut depth_texture = device
         .create_texture(&vn::TextureInfo {
+            label: None,
             dimension: vn::TextureDimension::D2,
             format: DEPTH_FORMAT,
             size: vn::Extent3D {
@@ -181,21 +176,19 @@ fn main() {
             samples: 1,
             usage: vn::TextureUsages::DEPTH_STENCIL_ATTACHMENT,
             sharing: vn::SharingMode::Exclusive,
+            queue_families: &[],
+            location: vn::MemoryLocation::Auto,
+            memory_priority: None,
         })
         .unwrap();
 
     let mut depth_view = device
         .create_texture_view(
             &vn::TextureViewInfo {
+                label: None,
                 dimension: vn::TextureViewDimension::D2,
                 format: DEPTH_FORMAT,
-                range: vn::ImageSubresourceRange {
-                    aspects: vn::TextureAspects::DEPTH,
-                    base_mip_level: 0,
-                    mip_level_count: 1,
-                    base_array_layer: 0,
-                    array_layer_count: 1,
-                },
+                range: DEPTH_RANGE,
             },
             &depth_texture,
         )
@@ -203,6 +196,7 @@ fn main() {
 
     let pipeline_layout = device
         .create_pipeline_layout(&vn::PipelineLayoutInfo {
+            label: None,
             flags: vn::PipelineLayoutFlags::empty(),
             bind_group_layouts: &[],
             push_constant_ranges: &[vn::PushConstantRange {
@@ -214,15 +208,19 @@ fn main() {
 
     let pipeline = device
         .create_raster_pipeline(&vn::RasterPipelineInfo {
+            label: None,
+            flags: vn::PipelineFlags::empty(),
             layout: &pipeline_layout,
             vertex: vn::ShaderStage {
                 module: &shader_vertex,
                 entry_point: "main",
+                constants: &[],
             },
-            vertex_buffers: &[Vertex::desc()],
+            vertex_buffers: &[Vertex::layout()],
             fragment: Some(vn::ShaderStage {
                 module: &shader_fragment,
                 entry_point: "main",
+                constants: &[],
             }),
             primitive: vn::PrimitiveState {
                 topology: vn::PrimitiveTopology::TriangleList,
@@ -259,6 +257,7 @@ fn main() {
                 }),
                 write_mask: vn::ColorWrites::ALL,
             }],
+            cache: None,
         })
         .unwrap();
 
@@ -286,6 +285,7 @@ fn main() {
 
                 depth_texture = device
                     .create_texture(&vn::TextureInfo {
+                        label: None,
                         dimension: vn::TextureDimension::D2,
                         format: DEPTH_FORMAT,
                         size: vn::Extent3D {
@@ -297,21 +297,19 @@ fn main() {
                         samples: 1,
                         usage: vn::TextureUsages::DEPTH_STENCIL_ATTACHMENT,
                         sharing: vn::SharingMode::Exclusive,
+                        queue_families: &[],
+                        location: vn::MemoryLocation::Auto,
+                        memory_priority: None,
                     })
                     .unwrap();
 
                 depth_view = device
                     .create_texture_view(
                         &vn::TextureViewInfo {
+                            label: None,
                             dimension: vn::TextureViewDimension::D2,
                             format: DEPTH_FORMAT,
-                            range: vn::ImageSubresourceRange {
-                                aspects: vn::TextureAspects::DEPTH,
-                                base_mip_level: 0,
-                                mip_level_count: 1,
-                                base_array_layer: 0,
-                                array_layer_count: 1,
-                            },
+                            range: DEPTH_RANGE,
                         },
                         &depth_texture,
                     )
@@ -334,18 +332,23 @@ fn main() {
                 .unwrap()
                 .unwrap();
 
-            let mut encoder = device.command_encoder(vn::CommandEncoderInfo { queue: &queue });
+            let mut encoder = device
+                .command_encoder(vn::CommandEncoderInfo {
+                    label: None,
+                    queue: &queue,
+                    tracking: false,
+                    frames_in_flight: 2,
+                })
+                .unwrap();
 
+            encoder.next_frame().unwrap();
             encoder.begin_encoding();
 
-            encoder.frame_transition(
-                vn::ImageTransitionLayout::Undefined,
-                vn::ImageTransitionLayout::ColorAttachment,
-                Some(vn::StageFlags::TOP_OF_PIPE),
-                None,
-                Some(vn::StageFlags::COLOR_ATTACHMENT_OUTPUT),
-                Some(vn::AccessFlags::COLOR_ATTACHMENT_WRITE),
-                &frame,
+            encoder.transition(frame.texture(), COLOR_RANGE, vn::TextureUses::COLOR_TARGET);
+            encoder.transition(
+                &depth_texture,
+                DEPTH_RANGE,
+                vn::TextureUses::DEPTH_STENCIL_WRITE,
             );
 
             encoder.begin_rendering(vn::RenderInfo {
@@ -371,6 +374,7 @@ fn main() {
                 stencil_attachment: None,
                 offset: (0, 0),
                 area: (surface_config.width, surface_config.height),
+                bundles: false,
             });
 
             encoder.bind_raster_pipeline(&pipeline);
@@ -405,15 +409,7 @@ fn main() {
 
             encoder.end_rendering();
 
-            encoder.frame_transition(
-                vn::ImageTransitionLayout::ColorAttachment,
-                vn::ImageTransitionLayout::Present,
-                Some(vn::StageFlags::COLOR_ATTACHMENT_OUTPUT),
-                Some(vn::AccessFlags::COLOR_ATTACHMENT_WRITE),
-                Some(vn::StageFlags::BOTTOM_OF_PIPE),
-                None,
-                &frame,
-            );
+            encoder.transition(frame.texture(), COLOR_RANGE, vn::TextureUses::PRESENT);
 
             queue
                 .submit(
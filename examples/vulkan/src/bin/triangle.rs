@@ -1,5 +1,6 @@
 use std::{io, mem};
 use vulkanite_vulkan::vn;
+use vulkanite_vulkan::vn::VertexLayout;
 
 use nalgebra_glm as na;
 use tracing::info;
@@ -9,7 +10,7 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, vn::VertexLayout)]
 struct Vertex {
     position: [f32; 3],
     color: [f32; 3],
@@ -30,27 +31,6 @@ const VERTICES: &[Vertex] = &[
     },
 ];
 
-impl Vertex {
-    pub fn desc<'a>() -> vn::VertexBufferLayout<'a> {
-        vn::VertexBufferLayout {
-            array_stride: mem::size_of::<Vertex>() as vn::BufferAddress,
-            step_mode: vn::VertexStepMode::Vertex,
-            attributes: &[
-                vn::VertexAttribute {
-                    format: vn::VertexFormat::Float32x3,
-                    offset: 0,
-                    location: 0,
-                },
-                vn::VertexAttribute {
-                    format: vn::VertexFormat::Float32x3,
-                    offset: mem::size_of::<[f32; 3]>() as vn::BufferAddress,
-                    location: 1,
-                },
-            ],
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct MeshPushConstants {
@@ -141,11 +121,15 @@ fn main() {
             contents: bytemuck::cast_slice(VERTICES),
             usage: vn::BufferUsages::VERTEX | vn::BufferUsages::MAP_WRITE,
             sharing: vn::SharingMode::Exclusive,
+            queue_families: &[],
+            location: vn::MemoryLocation::Auto,
+            memory_priority: None,
         })
         .unwrap();
 
     let pipeline_layout = device
         .create_pipeline_layout(&vn::PipelineLayoutInfo {
+            label: None,
             flags: vn::PipelineLayoutFlags::empty(),
             bind_group_layouts: &[],
             push_constant_ranges: &[vn::PushConstantRange {
@@ -157,15 +141,19 @@ fn main() {
 
     let pipeline = device
         .create_raster_pipeline(&vn::RasterPipelineInfo {
+            label: None,
+            flags: vn::PipelineFlags::empty(),
             layout: &pipeline_layout,
             vertex: vn::ShaderStage {
                 module: &shader_vertex,
                 entry_point: "main",
+                constants: &[],
             },
-            vertex_buffers: &[Vertex::desc()],
+            vertex_buffers: &[Vertex::layout()],
             fragment: Some(vn::ShaderStage {
                 module: &shader_fragment,
                 entry_point: "main",
+                constants: &[],
             }),
             primitive: vn::PrimitiveState {
                 topology: vn::PrimitiveTopology::TriangleList,
@@ -191,6 +179,7 @@ fn main() {
                 }),
                 write_mask: vn::ColorWrites::ALL,
             }],
+            cache: None,
         })
         .unwrap();
 
@@ -233,8 +222,16 @@ fn main() {
                 .unwrap()
                 .unwrap();
 
-            let mut encoder = device.command_encoder(vn::CommandEncoderInfo { queue: &queue });
+            let mut encoder = device
+                .command_encoder(vn::CommandEncoderInfo {
+                    label: None,
+                    queue: &queue,
+                    tracking: false,
+                    frames_in_flight: 2,
+                })
+                .unwrap();
 
+            encoder.next_frame().unwrap();
             encoder.begin_encoding();
 
             encoder.frame_transition(
@@ -259,6 +256,7 @@ fn main() {
                 stencil_attachment: None,
                 offset: (0, 0),
                 area: (surface_config.width, surface_config.height),
+                bundles: false,
             });
 
             encoder.bind_raster_pipeline(&pipeline);